@@ -1,6 +1,6 @@
 use core::convert::From;
 
-use crate::{primitive::{Body, Vec2, Shape}, util::lerp};
+use crate::{math, primitive::{Body, Vec2, Shape}, util::lerp};
 use super::partition::{Dimension, Partition};
 
 #[derive(Clone, Copy, Debug, Default)]
@@ -51,6 +51,44 @@ impl Aabb {
         Vec2::new_at(self.right() - self.left(), self.top() - self.bottom())
     }
 
+    pub fn from_point(point: &Vec2) -> Self {
+        Self::new(point.x, point.x, point.y, point.y)
+    }
+
+    // Bounding box of the ray segment, also for infinite rays
+    pub fn from_ray(origin: &Vec2, dir: &Vec2, max_toi: f64) -> Self {
+        let end_x = if dir.x == 0.0 { origin.x } else { origin.x + dir.x * max_toi };
+        let end_y = if dir.y == 0.0 { origin.y } else { origin.y + dir.y * max_toi };
+
+        Self::new(
+            origin.x.min(end_x),
+            origin.x.max(end_x),
+            origin.y.max(end_y),
+            origin.y.min(end_y),
+        )
+    }
+
+    pub fn contains(&self, rhs: &Self) -> bool {
+        self.left() <= rhs.left() &&
+        rhs.right() <= self.right() &&
+        self.bottom() <= rhs.bottom() &&
+        rhs.top() <= self.top()
+    }
+
+    pub fn inflated(&self, margin: f64) -> Self {
+        Self::new(
+            self.left() - margin,
+            self.right() + margin,
+            self.top() + margin,
+            self.bottom() - margin,
+        )
+    }
+
+    pub fn perimeter(&self) -> f64 {
+        let extent = self.extent();
+        2.0 * (extent.x + extent.y)
+    }
+
     pub fn intersects(&self, rhs: &Self) -> bool {
         self.left() <= rhs.right() &&
         rhs.left() <= self.right() &&
@@ -58,6 +96,41 @@ impl Aabb {
         rhs.bottom() <= self.top()
     }
 
+    // Slab test, returns the parameter along `dir` at which the ray enters the box
+    pub fn ray_intersection(&self, origin: &Vec2, dir: &Vec2, max_toi: f64) -> Option<f64> {
+        let mut t_min: f64 = 0.0;
+        let mut t_max = max_toi;
+
+        let slabs = [
+            (origin.x, dir.x, self.left(), self.right()),
+            (origin.y, dir.y, self.bottom(), self.top()),
+        ];
+
+        for (origin, dir, min, max) in slabs {
+            if dir == 0.0 {
+                if origin < min || origin > max {
+                    return None;
+                }
+                continue;
+            }
+
+            let inverse_dir = 1.0 / dir;
+            let mut t_near = (min - origin) * inverse_dir;
+            let mut t_far = (max - origin) * inverse_dir;
+            if t_near > t_far {
+                std::mem::swap(&mut t_near, &mut t_far);
+            }
+
+            t_min = t_min.max(t_near);
+            t_max = t_max.min(t_far);
+            if t_min > t_max {
+                return None;
+            }
+        }
+
+        Some(t_min)
+    }
+
     pub fn center(&self) -> Vec2 {
         Vec2::new_at(
             lerp(self.left(), self.right(), 0.5),
@@ -65,7 +138,7 @@ impl Aabb {
         )
     }
 
-    pub fn merge(&mut self, other: &Self) -> Self {
+    pub fn merge(&self, other: &Self) -> Self {
         Self::new(
             self.left().min(other.left()),
             self.right().max(other.right()),
@@ -125,10 +198,15 @@ impl From<&Body> for Aabb {
                 top_left: body.position + Vec2::new_at(-*radius, *radius),
                 bottom_right: body.position + Vec2::new_at(*radius,- *radius),
             },
-            Shape::Square { edge_length } => Self {
-                top_left: body.position + Vec2::new_at(-edge_length / 2.0, edge_length / 2.0),
-                bottom_right: body.position + Vec2::new_at(edge_length / 2.0, -edge_length / 2.0),
-            },
+            Shape::Square { edge_length } => {
+                // A rotated square reaches further out along both axes
+                let (sin, cos) = math::sin_cos(body.angle);
+                let half_extent = edge_length / 2.0 * (cos.abs() + sin.abs());
+                Self {
+                    top_left: body.position + Vec2::new_at(-half_extent, half_extent),
+                    bottom_right: body.position + Vec2::new_at(half_extent, -half_extent),
+                }
+            }
         }
     }
 }
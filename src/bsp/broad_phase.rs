@@ -0,0 +1,264 @@
+use crate::primitive::Vec2;
+
+use super::{
+    aabb::Aabb,
+    dynamic_tree::DynamicTree,
+    entry::{Entry, EntrySet},
+};
+
+// Brings pairs into canonical `(lower id, higher id)` order, sorted and without duplicates,
+// so the solver sees each contact once and independent of any hash map iteration order.
+// A body is never paired with itself
+pub fn canonicalize_pairs(pairs: &mut Vec<(usize, usize)>) {
+    pairs.retain(|(lhs, rhs)| lhs != rhs);
+    for pair in pairs.iter_mut() {
+        if pair.0 > pair.1 {
+            *pair = (pair.1, pair.0);
+        }
+    }
+    pairs.sort_unstable();
+    pairs.dedup();
+}
+
+pub trait BroadPhaseClone {
+    fn clone_box(&self) -> Box<dyn BroadPhase>;
+}
+
+impl<T: BroadPhase + Clone + 'static> BroadPhaseClone for T {
+    fn clone_box(&self) -> Box<dyn BroadPhase> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn BroadPhase> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+pub trait BroadPhase: BroadPhaseClone {
+    fn insert(&mut self, id: usize, aabb: Aabb);
+    fn remove(&mut self, id: usize);
+    fn update(&mut self, id: usize, aabb: Aabb);
+    // Canonical pairs, see `canonicalize_pairs`
+    fn candidate_pairs(&mut self) -> Vec<(usize, usize)>;
+    fn query_aabb(&self, aabb: &Aabb) -> Vec<usize>;
+
+    // Candidates along with a lower bound of their time of impact
+    fn ray_candidates(&self, origin: &Vec2, dir: &Vec2, max_toi: f64) -> Vec<(usize, f64)> {
+        self.query_aabb(&Aabb::from_ray(origin, dir, max_toi))
+            .into_iter()
+            .map(|id| (id, 0.0))
+            .collect()
+    }
+}
+
+impl BroadPhase for DynamicTree {
+    fn insert(&mut self, id: usize, aabb: Aabb) {
+        DynamicTree::insert(self, id, aabb);
+    }
+
+    fn remove(&mut self, id: usize) {
+        DynamicTree::remove(self, id);
+    }
+
+    fn update(&mut self, id: usize, aabb: Aabb) {
+        DynamicTree::update(self, id, aabb);
+    }
+
+    fn candidate_pairs(&mut self) -> Vec<(usize, usize)> {
+        self.internal_collisions()
+    }
+
+    fn query_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        DynamicTree::query_aabb(self, aabb)
+    }
+
+    fn ray_candidates(&self, origin: &Vec2, dir: &Vec2, max_toi: f64) -> Vec<(usize, f64)> {
+        DynamicTree::ray_candidates(self, origin, dir, max_toi)
+    }
+}
+
+// Structures which can only be built in one go from all entries
+pub trait StaticBroadPhase {
+    fn build(entries: Vec<Entry>) -> Self;
+    fn collect_pairs(&self) -> Vec<(usize, usize)>;
+    fn query_aabb(&self, aabb: &Aabb) -> Vec<usize>;
+
+    fn ray_candidates(&self, origin: &Vec2, dir: &Vec2, max_toi: f64) -> Vec<(usize, f64)> {
+        self.query_aabb(&Aabb::from_ray(origin, dir, max_toi))
+            .into_iter()
+            .map(|id| (id, 0.0))
+            .collect()
+    }
+}
+
+// Adapts a static structure to the incremental interface by rebuilding it
+// whenever the entries changed
+pub struct Rebuilding<S: StaticBroadPhase> {
+    entries: EntrySet,
+    structure: Option<S>,
+}
+
+impl<S: StaticBroadPhase> Rebuilding<S> {
+    pub fn new() -> Self {
+        Self {
+            entries: EntrySet::new(),
+            structure: None,
+        }
+    }
+}
+
+// The structure gets rebuilt from the same entries on demand, so a copy finds the same pairs
+impl<S: StaticBroadPhase> Clone for Rebuilding<S> {
+    fn clone(&self) -> Self {
+        Self {
+            entries: self.entries.clone(),
+            structure: None,
+        }
+    }
+}
+
+impl<S: StaticBroadPhase> Default for Rebuilding<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: StaticBroadPhase + 'static> BroadPhase for Rebuilding<S> {
+    fn insert(&mut self, id: usize, aabb: Aabb) {
+        self.entries.insert(id, aabb);
+        self.structure = None;
+    }
+
+    fn remove(&mut self, id: usize) {
+        if self.entries.remove(id).is_some() {
+            self.structure = None;
+        }
+    }
+
+    fn update(&mut self, id: usize, aabb: Aabb) {
+        self.insert(id, aabb);
+    }
+
+    fn candidate_pairs(&mut self) -> Vec<(usize, usize)> {
+        self.structure
+            .get_or_insert_with(|| S::build(self.entries.entries().to_vec()))
+            .collect_pairs()
+    }
+
+    fn query_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        match &self.structure {
+            Some(structure) => structure.query_aabb(aabb),
+            None => self
+                .entries
+                .entries()
+                .iter()
+                .filter(|e| e.aabb.intersects(aabb))
+                .map(|e| e.id)
+                .collect(),
+        }
+    }
+
+    fn ray_candidates(&self, origin: &Vec2, dir: &Vec2, max_toi: f64) -> Vec<(usize, f64)> {
+        match &self.structure {
+            Some(structure) => structure.ray_candidates(origin, dir, max_toi),
+            None => self
+                .entries
+                .entries()
+                .iter()
+                .filter_map(|e| Some((e.id, e.aabb.ray_intersection(origin, dir, max_toi)?)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsp::{
+        grid::Grid,
+        quadtree::{Quadtree, QuadtreeConfig},
+        spatial_hash::SpatialHash, sweep_and_prune::SweepAndPrune,
+        tree::Tree,
+    };
+
+    fn entries() -> Vec<Entry> {
+        (0..50)
+            .map(|id| {
+                let x = (id * 37 % 100) as f64;
+                let y = (id * 61 % 100) as f64;
+                let size = (id % 7 + 3) as f64;
+                Entry { id, aabb: Aabb::new(x, x + size, y + size, y) }
+            })
+            .collect()
+    }
+
+    fn sorted_pairs(broadphase: &mut dyn BroadPhase) -> Vec<(usize, usize)> {
+        for entry in entries() {
+            broadphase.insert(entry.id, entry.aabb);
+        }
+
+        broadphase.candidate_pairs()
+    }
+
+    #[test]
+    fn test_backends_agree() {
+        let entries = entries();
+        let mut expected = vec![];
+        for lhs in &entries {
+            for rhs in &entries {
+                if lhs.id < rhs.id && lhs.aabb.intersects(&rhs.aabb) {
+                    expected.push((lhs.id, rhs.id));
+                }
+            }
+        }
+
+        assert!(!expected.is_empty());
+        assert_eq!(expected, sorted_pairs(&mut Rebuilding::<Tree>::new()));
+        assert_eq!(expected, sorted_pairs(&mut Rebuilding::<Grid<4, 4>>::new()));
+        assert_eq!(expected, sorted_pairs(&mut Rebuilding::<Grid<5, 3>>::new()));
+        assert_eq!(expected, sorted_pairs(&mut SpatialHash::new(8.0)));
+        assert_eq!(expected, sorted_pairs(&mut Quadtree::default()));
+        assert_eq!(expected, sorted_pairs(&mut SweepAndPrune::new()));
+        // Without a fat margin the dynamic tree reports exactly the overlapping pairs
+        let mut tree = DynamicTree::new(0.0);
+        assert_eq!(expected, sorted_pairs(&mut tree));
+    }
+
+    #[test]
+    fn test_sweep_and_prune_reinsert() {
+        let mut sweep_and_prune = SweepAndPrune::new();
+        sweep_and_prune.insert(0, Aabb::new(0.0, 1.0, 1.0, 0.0));
+        sweep_and_prune.candidate_pairs();
+        sweep_and_prune.remove(0);
+        sweep_and_prune.insert(0, Aabb::new(0.0, 1.0, 1.0, 0.0));
+        assert!(sweep_and_prune.candidate_pairs().is_empty());
+
+        sweep_and_prune.insert(1, Aabb::new(0.5, 1.5, 1.0, 0.0));
+        assert_eq!(vec![(0, 1)], sweep_and_prune.candidate_pairs());
+    }
+
+    #[test]
+    fn test_quadtree_pairs_across_quadrants() {
+        let mut quadtree = Quadtree::new(QuadtreeConfig {
+            capacity: 1,
+            max_depth: 8,
+            looseness: 2.0,
+        });
+        // The root gets centered on the first box, the others straddle its quadrant boundaries
+        quadtree.insert(0, Aabb::new(-1.0, 1.0, 1.0, -1.0));
+        let offsets = [(-0.6, -0.6), (0.6, -0.6), (-0.6, 0.6), (0.6, 0.6)];
+        for (id, (x, y)) in offsets.into_iter().enumerate() {
+            quadtree.insert(id + 1, Aabb::new(x - 0.8, x + 0.8, y + 0.8, y - 0.8));
+        }
+
+        let root = quadtree.node(quadtree.root().unwrap());
+        assert!(root.children().is_some());
+        // All of them overlap each other
+        let expected: Vec<_> = (0..5)
+            .flat_map(|lhs| (lhs + 1..5).map(move |rhs| (lhs, rhs)))
+            .collect();
+        assert_eq!(expected, quadtree.internal_collisions());
+    }
+}
@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+
+use crate::primitive::Vec2;
+
+use super::{aabb::Aabb, broad_phase::canonicalize_pairs};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+const NULL_NODE: usize = usize::MAX;
+const DEFAULT_FAT_AABB_MARGIN: f64 = 2.0;
+
+#[derive(Clone, Copy)]
+struct TreeNode {
+    aabb: Aabb,
+    parent: usize,
+    children: [usize; 2],
+    // Leaves have height 0, free nodes -1
+    height: i32,
+    id: usize,
+}
+
+impl TreeNode {
+    fn is_leaf(&self) -> bool {
+        self.children[0] == NULL_NODE
+    }
+}
+
+impl Default for TreeNode {
+    fn default() -> Self {
+        Self {
+            aabb: Aabb::default(),
+            parent: NULL_NODE,
+            children: [NULL_NODE, NULL_NODE],
+            height: -1,
+            id: usize::MAX,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct DynamicTree {
+    nodes: Vec<TreeNode>,
+    free_nodes: Vec<usize>,
+    root: usize,
+    leaves: HashMap<usize, usize>,
+    margin: f64,
+}
+
+impl Default for DynamicTree {
+    fn default() -> Self {
+        Self::new(DEFAULT_FAT_AABB_MARGIN)
+    }
+}
+
+impl DynamicTree {
+    pub fn new(margin: f64) -> Self {
+        Self {
+            nodes: vec![],
+            free_nodes: vec![],
+            root: NULL_NODE,
+            leaves: HashMap::new(),
+            margin,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn height(&self) -> i32 {
+        if self.root == NULL_NODE {
+            return 0;
+        }
+        self.nodes[self.root].height
+    }
+
+    pub fn fat_aabb(&self, id: usize) -> Option<Aabb> {
+        self.leaves.get(&id).map(|leaf| self.nodes[*leaf].aabb)
+    }
+
+    pub fn insert(&mut self, id: usize, aabb: Aabb) {
+        self.remove(id);
+
+        let leaf = self.allocate_node();
+        self.nodes[leaf] = TreeNode {
+            aabb: aabb.inflated(self.margin),
+            height: 0,
+            id,
+            ..TreeNode::default()
+        };
+
+        self.insert_leaf(leaf);
+        self.leaves.insert(id, leaf);
+    }
+
+    pub fn remove(&mut self, id: usize) {
+        if let Some(leaf) = self.leaves.remove(&id) {
+            self.remove_leaf(leaf);
+            self.free_node(leaf);
+        }
+    }
+
+    // Only restructures the tree if the entry escaped its fat AABB, returns whether it did
+    pub fn update(&mut self, id: usize, aabb: Aabb) -> bool {
+        let Some(&leaf) = self.leaves.get(&id) else {
+            self.insert(id, aabb);
+            return true;
+        };
+
+        if self.nodes[leaf].aabb.contains(&aabb) {
+            return false;
+        }
+
+        self.remove_leaf(leaf);
+        self.nodes[leaf].aabb = aabb.inflated(self.margin);
+        self.insert_leaf(leaf);
+        true
+    }
+
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        let mut found = vec![];
+        self.visit(
+            |node_aabb| node_aabb.intersects(aabb),
+            |node| found.push(node.id),
+        );
+        found
+    }
+
+    pub fn ray_candidates(&self, origin: &Vec2, dir: &Vec2, max_toi: f64) -> Vec<(usize, f64)> {
+        let mut candidates = vec![];
+        let mut nodes_to_search = vec![];
+        if self.root != NULL_NODE {
+            nodes_to_search.push(self.root);
+        }
+
+        while let Some(index) = nodes_to_search.pop() {
+            let node = &self.nodes[index];
+            let Some(toi) = node.aabb.ray_intersection(origin, dir, max_toi) else {
+                continue;
+            };
+
+            if node.is_leaf() {
+                candidates.push((node.id, toi));
+            } else {
+                nodes_to_search.extend(node.children);
+            }
+        }
+
+        candidates
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    pub fn internal_collisions(&self) -> Vec<(usize, usize)> {
+        let mut collisions = vec![];
+        for node in self.nodes.iter().filter(|n| n.height == 0) {
+            self.leaf_collisions(node, &mut collisions);
+        }
+        canonicalize_pairs(&mut collisions);
+        collisions
+    }
+
+    #[cfg(feature = "parallel")]
+    pub fn internal_collisions(&self) -> Vec<(usize, usize)> {
+        let mut collisions: Vec<(usize, usize)> = self
+            .nodes
+            .par_iter()
+            .filter(|n| n.height == 0)
+            .fold(Vec::new, |mut collisions, node| {
+                self.leaf_collisions(node, &mut collisions);
+                collisions
+            })
+            .flatten_iter()
+            .collect();
+        canonicalize_pairs(&mut collisions);
+        collisions
+    }
+
+    fn leaf_collisions(&self, node: &TreeNode, collisions: &mut Vec<(usize, usize)>) {
+        self.visit(
+            |node_aabb| node_aabb.intersects(&node.aabb),
+            |other| {
+                // Every pair is found from both sides, only keep one of them
+                if node.id < other.id {
+                    collisions.push((node.id, other.id));
+                }
+            },
+        );
+    }
+
+    fn visit(&self, mut descend: impl FnMut(&Aabb) -> bool, mut on_leaf: impl FnMut(&TreeNode)) {
+        let mut nodes_to_search = vec![];
+        if self.root != NULL_NODE {
+            nodes_to_search.push(self.root);
+        }
+
+        while let Some(index) = nodes_to_search.pop() {
+            let node = &self.nodes[index];
+            if !descend(&node.aabb) {
+                continue;
+            }
+
+            if node.is_leaf() {
+                on_leaf(node);
+            } else {
+                nodes_to_search.extend(node.children);
+            }
+        }
+    }
+
+    fn allocate_node(&mut self) -> usize {
+        if let Some(index) = self.free_nodes.pop() {
+            return index;
+        }
+
+        self.nodes.push(TreeNode::default());
+        self.nodes.len() - 1
+    }
+
+    fn free_node(&mut self, index: usize) {
+        self.nodes[index] = TreeNode::default();
+        self.free_nodes.push(index);
+    }
+
+    fn insert_leaf(&mut self, leaf: usize) {
+        if self.root == NULL_NODE {
+            self.root = leaf;
+            self.nodes[leaf].parent = NULL_NODE;
+            return;
+        }
+
+        // Find the cheapest sibling using the surface area heuristic
+        let leaf_aabb = self.nodes[leaf].aabb;
+        let mut index = self.root;
+        while !self.nodes[index].is_leaf() {
+            let node = self.nodes[index];
+            let perimeter = node.aabb.perimeter();
+            let combined_perimeter = node.aabb.merge(&leaf_aabb).perimeter();
+
+            // Cost of creating a new parent for this node and the new leaf
+            let cost = 2.0 * combined_perimeter;
+            // Minimum cost of pushing the leaf further down the tree
+            let inheritance_cost = 2.0 * (combined_perimeter - perimeter);
+
+            let descend_cost = |child: usize| {
+                let child = &self.nodes[child];
+                let merged_perimeter = child.aabb.merge(&leaf_aabb).perimeter();
+                if child.is_leaf() {
+                    merged_perimeter + inheritance_cost
+                } else {
+                    merged_perimeter - child.aabb.perimeter() + inheritance_cost
+                }
+            };
+
+            let [lhs, rhs] = node.children;
+            let lhs_cost = descend_cost(lhs);
+            let rhs_cost = descend_cost(rhs);
+
+            if cost < lhs_cost && cost < rhs_cost {
+                break;
+            }
+
+            index = if lhs_cost < rhs_cost { lhs } else { rhs };
+        }
+
+        let sibling = index;
+        let old_parent = self.nodes[sibling].parent;
+        let new_parent = self.allocate_node();
+        self.nodes[new_parent] = TreeNode {
+            aabb: leaf_aabb.merge(&self.nodes[sibling].aabb),
+            parent: old_parent,
+            children: [sibling, leaf],
+            height: self.nodes[sibling].height + 1,
+            id: usize::MAX,
+        };
+
+        if old_parent == NULL_NODE {
+            self.root = new_parent;
+        } else {
+            self.replace_child(old_parent, sibling, new_parent);
+        }
+
+        self.nodes[sibling].parent = new_parent;
+        self.nodes[leaf].parent = new_parent;
+
+        self.refit_ancestors(new_parent);
+    }
+
+    fn remove_leaf(&mut self, leaf: usize) {
+        if leaf == self.root {
+            self.root = NULL_NODE;
+            return;
+        }
+
+        let parent = self.nodes[leaf].parent;
+        let grand_parent = self.nodes[parent].parent;
+        let [lhs, rhs] = self.nodes[parent].children;
+        let sibling = if lhs == leaf { rhs } else { lhs };
+
+        self.free_node(parent);
+        self.nodes[leaf].parent = NULL_NODE;
+
+        if grand_parent == NULL_NODE {
+            self.root = sibling;
+            self.nodes[sibling].parent = NULL_NODE;
+        } else {
+            self.replace_child(grand_parent, parent, sibling);
+            self.nodes[sibling].parent = grand_parent;
+            self.refit_ancestors(grand_parent);
+        }
+    }
+
+    fn replace_child(&mut self, parent: usize, old_child: usize, new_child: usize) {
+        let children = &mut self.nodes[parent].children;
+        if children[0] == old_child {
+            children[0] = new_child;
+        } else {
+            children[1] = new_child;
+        }
+    }
+
+    fn refit(&mut self, index: usize) {
+        let [lhs, rhs] = self.nodes[index].children;
+        let (lhs, rhs) = (self.nodes[lhs], self.nodes[rhs]);
+        self.nodes[index].height = 1 + lhs.height.max(rhs.height);
+        self.nodes[index].aabb = lhs.aabb.merge(&rhs.aabb);
+    }
+
+    fn refit_ancestors(&mut self, mut index: usize) {
+        while index != NULL_NODE {
+            index = self.balance(index);
+            self.refit(index);
+            index = self.nodes[index].parent;
+        }
+    }
+
+    // Performs a tree rotation if the subtree at `a` is imbalanced, returns the new subtree root
+    fn balance(&mut self, a: usize) -> usize {
+        if self.nodes[a].is_leaf() || self.nodes[a].height < 2 {
+            return a;
+        }
+
+        let [b, c] = self.nodes[a].children;
+        let balance = self.nodes[c].height - self.nodes[b].height;
+
+        if balance > 1 {
+            self.rotate_up(a, c, 1);
+            return c;
+        }
+
+        if balance < -1 {
+            self.rotate_up(a, b, 0);
+            return b;
+        }
+
+        a
+    }
+
+    // Swaps `child` (stored at `slot` of `a`) with `a`, leaving `a` below it with the
+    // lower of `child`'s children
+    fn rotate_up(&mut self, a: usize, child: usize, slot: usize) {
+        let [f, g] = self.nodes[child].children;
+        let a_parent = self.nodes[a].parent;
+
+        self.nodes[child].children[0] = a;
+        self.nodes[child].parent = a_parent;
+        self.nodes[a].parent = child;
+
+        if a_parent == NULL_NODE {
+            self.root = child;
+        } else {
+            self.replace_child(a_parent, a, child);
+        }
+
+        let (kept, moved) = if self.nodes[f].height > self.nodes[g].height {
+            (f, g)
+        } else {
+            (g, f)
+        };
+
+        self.nodes[child].children[1] = kept;
+        self.nodes[a].children[slot] = moved;
+        self.nodes[moved].parent = a;
+
+        self.refit(a);
+        self.refit(child);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_update_remove() {
+        let mut tree = DynamicTree::new(1.0);
+        for id in 0..64 {
+            let x = id as f64 * 3.0;
+            tree.insert(id, Aabb::new(x, x + 2.0, 2.0, 0.0));
+        }
+        assert_eq!(64, tree.len());
+        assert!(tree.height() <= 10);
+
+        // Adjacent boxes are 1 apart, so the fat boxes overlap with their neighbours
+        assert_eq!(63, tree.internal_collisions().len());
+
+        assert!(!tree.update(0, Aabb::new(0.5, 2.5, 2.0, 0.0)));
+        assert!(tree.update(0, Aabb::new(500.0, 502.0, 2.0, 0.0)));
+        assert_eq!(vec![0], tree.query_aabb(&Aabb::new(499.0, 501.0, 1.0, 0.0)));
+
+        for id in 0..64 {
+            tree.remove(id);
+        }
+        assert!(tree.is_empty());
+        assert!(tree.query_aabb(&Aabb::new(0.0, 1000.0, 10.0, 0.0)).is_empty());
+    }
+}
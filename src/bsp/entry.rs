@@ -1,7 +1,57 @@
+use std::collections::HashMap;
+
 use super::aabb::Aabb;
 
 #[derive(Clone, Copy, Default)]
 pub struct Entry {
     pub id: usize,
     pub aabb: Aabb,
-}
\ No newline at end of file
+}
+
+// Entries addressable by id, stored densely so they can be handed to the static structures
+#[derive(Clone, Default)]
+pub struct EntrySet {
+    entries: Vec<Entry>,
+    indices: HashMap<usize, usize>,
+}
+
+impl EntrySet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, id: usize, aabb: Aabb) {
+        if let Some(index) = self.indices.get(&id) {
+            self.entries[*index].aabb = aabb;
+            return;
+        }
+
+        self.indices.insert(id, self.entries.len());
+        self.entries.push(Entry { id, aabb });
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Entry> {
+        let index = self.indices.remove(&id)?;
+        let entry = self.entries.swap_remove(index);
+        if let Some(moved) = self.entries.get(index) {
+            self.indices.insert(moved.id, index);
+        }
+        Some(entry)
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Entry> {
+        self.indices.get(&id).map(|index| &self.entries[*index])
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+}
@@ -1,6 +1,10 @@
 use std::array;
 
-use super::{aabb::Aabb, entry::Entry};
+use super::{
+    aabb::Aabb,
+    broad_phase::{canonicalize_pairs, StaticBroadPhase},
+    entry::Entry,
+};
 
 pub struct Grid<const X: usize, const Y: usize> {
     entries: Vec<Entry>,
@@ -25,13 +29,13 @@ impl<const X: usize, const Y: usize> Grid<X, Y> {
             if max_x == X {
                 max_x -= 1;
             }
-            if max_y == X {
+            if max_y == Y {
                 max_y -= 1;
             }
 
-            for x in min_x..max_x+1 {
-                for y in min_y..max_y+1 {
-                    cells[x][y].push(index);
+            for row in &mut cells[min_y..=max_y] {
+                for cell in &mut row[min_x..=max_x] {
+                    cell.push(index);
                 }
             }
         }
@@ -47,10 +51,7 @@ impl<const X: usize, const Y: usize> Grid<X, Y> {
         for row in &self.cells {
             for cell in row {
                 for i in 0..cell.len() {
-                    for j in 0..cell.len() {
-                        if i == j {
-                            continue;
-                        }
+                    for j in i+1..cell.len() {
 
                         let lhs = &self.entries[cell[i]];
                         let rhs = &self.entries[cell[j]];
@@ -61,6 +62,56 @@ impl<const X: usize, const Y: usize> Grid<X, Y> {
                 }
             }
         }
+
+        // Entries spanning multiple cells are paired up in each of them
+        canonicalize_pairs(found_collisions);
+    }
+
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        if !aabb.intersects(&self.aabb) {
+            return vec![];
+        }
+
+        let cell_width = self.aabb.extent().x / X as f64;
+        let cell_height = self.aabb.extent().y / Y as f64;
+
+        let min_x = ((aabb.left() - self.aabb.left()) / cell_width).floor().max(0.0) as usize;
+        let max_x = (((aabb.right() - self.aabb.left()) / cell_width).floor() as usize).min(X - 1);
+        let min_y = ((aabb.bottom() - self.aabb.bottom()) / cell_height).floor().max(0.0) as usize;
+        let max_y = (((aabb.top() - self.aabb.bottom()) / cell_height).floor() as usize).min(Y - 1);
+
+        let mut found = vec![];
+        for x in min_x..max_x+1 {
+            for y in min_y..max_y+1 {
+                found.extend(
+                    self.cells[y][x]
+                        .iter()
+                        .map(|index| &self.entries[*index])
+                        .filter(|e| e.aabb.intersects(aabb))
+                        .map(|e| e.id),
+                );
+            }
+        }
+
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+}
+
+impl<const X: usize, const Y: usize> StaticBroadPhase for Grid<X, Y> {
+    fn build(entries: Vec<Entry>) -> Self {
+        Self::new(entries)
+    }
+
+    fn collect_pairs(&self) -> Vec<(usize, usize)> {
+        let mut collisions = vec![];
+        self.collect_collisions(&mut collisions);
+        collisions
+    }
+
+    fn query_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        Grid::query_aabb(self, aabb)
     }
 }
 
@@ -1,8 +1,12 @@
 mod partition;
 mod node;
-mod quadtree;
 
 pub mod aabb;
+pub mod broad_phase;
 pub mod tree;
+pub mod dynamic_tree;
 pub mod entry;
-mod grid;
+pub mod grid;
+pub mod quadtree;
+pub mod spatial_hash;
+pub mod sweep_and_prune;
@@ -6,6 +6,9 @@ use super::{
 };
 
 const MAX_NUM_ENTRIES: usize = 2;
+// Below this many entries spawning tasks costs more than it saves
+#[cfg(feature = "parallel")]
+const PARALLEL_BUILD_THRESHOLD: usize = 512;
 
 pub struct Children {
     pub left_child: Box<Node>,
@@ -88,14 +91,32 @@ impl Node {
         }
 
         let entries_aabb = Aabb::merged(mid_entries.iter().map(|e| e.aabb));
+        let (left_child, right_child) = Self::new_children(left_entries, right_entries, -dimension);
         Self {
             entries: mid_entries,
             entries_aabb,
             children: Some(Children {
-                left_child: Box::new(Self::new_with(left_entries, -dimension)),
-                right_child: Box::new(Self::new_with(right_entries, -dimension)),
+                left_child: Box::new(left_child),
+                right_child: Box::new(right_child),
             }),
             partition: Some(partition),
         }
     }
+
+    #[cfg(not(feature = "parallel"))]
+    fn new_children(left: Vec<Entry>, right: Vec<Entry>, dimension: Dimension) -> (Self, Self) {
+        (Self::new_with(left, dimension), Self::new_with(right, dimension))
+    }
+
+    #[cfg(feature = "parallel")]
+    fn new_children(left: Vec<Entry>, right: Vec<Entry>, dimension: Dimension) -> (Self, Self) {
+        if left.len() + right.len() < PARALLEL_BUILD_THRESHOLD {
+            return (Self::new_with(left, dimension), Self::new_with(right, dimension));
+        }
+
+        rayon::join(
+            || Self::new_with(left, dimension),
+            || Self::new_with(right, dimension),
+        )
+    }
 }
@@ -1,108 +1,438 @@
-use super::{aabb::Aabb, entry::Entry};
+use std::collections::HashMap;
 
-#[derive(Default, Clone)]
-struct Node {
+use crate::primitive::Vec2;
+
+use super::{
+    aabb::Aabb,
+    broad_phase::{canonicalize_pairs, BroadPhase},
+    entry::Entry,
+};
+
+const MAX_ROOT_GROWTH: usize = 64;
+
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QuadtreeConfig {
+    // Number of entries a node holds before it is subdivided
+    pub capacity: usize,
+    pub max_depth: usize,
+    // Factor by which the loose bounds of a node exceed its tight bounds
+    pub looseness: f64,
+}
+
+impl Default for QuadtreeConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 4,
+            max_depth: 8,
+            looseness: 2.0,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct QuadtreeNode {
+    center: Vec2,
+    half_size: f64,
+    parent: Option<usize>,
     entries: Vec<Entry>,
-    aabb: Aabb,
-    children: Option<Box<[Node; 4]>>,
+    children: Option<[usize; 4]>,
+}
+
+impl QuadtreeNode {
+    pub fn center(&self) -> Vec2 {
+        self.center
+    }
+
+    pub const fn half_size(&self) -> f64 {
+        self.half_size
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub const fn children(&self) -> Option<[usize; 4]> {
+        self.children
+    }
+
+    fn quadrant(&self, point: &Vec2) -> usize {
+        usize::from(point.x >= self.center.x) + 2 * usize::from(point.y >= self.center.y)
+    }
+
+    fn contains_point(&self, point: &Vec2) -> bool {
+        (point.x - self.center.x).abs() <= self.half_size
+            && (point.y - self.center.y).abs() <= self.half_size
+    }
 }
 
-pub struct Quadtree<const S: usize> {
+#[derive(Clone)]
+pub struct Quadtree {
+    config: QuadtreeConfig,
+    nodes: Vec<QuadtreeNode>,
+    free_nodes: Vec<usize>,
+    root: Option<usize>,
+    locations: HashMap<usize, usize>,
+}
 
-    root: Box<Node>,
+impl Default for Quadtree {
+    fn default() -> Self {
+        Self::new(QuadtreeConfig::default())
+    }
 }
 
-impl Node {
-    pub fn new(entries: Vec<Entry>, depth: usize) -> Self {
-        let aabb = Aabb::merged(entries.iter().map(|e| e.aabb));
-        if entries.len() <= 3 || depth >= 5 {
-            return Self {
-                entries,
-                aabb: aabb.unwrap_or_default(),
-                children: None,
-            };
+impl Quadtree {
+    pub fn new(config: QuadtreeConfig) -> Self {
+        assert!(config.capacity > 0, "Capacity must be positive!");
+        assert!(config.looseness > 1.0, "Looseness must be greater than one!");
+
+        Self {
+            config,
+            nodes: vec![],
+            free_nodes: vec![],
+            root: None,
+            locations: HashMap::new(),
         }
+    }
 
-        if let Some(aabb) = aabb {
-            let tl_aabb = Aabb::new(aabb.left(), aabb.center().x, aabb.top(), aabb.center().y);
-            let tr_aabb = Aabb::new(aabb.center().x, aabb.right(), aabb.top(), aabb.center().y);
-            let bl_aabb = Aabb::new(aabb.left(), aabb.center().x, aabb.center().y, aabb.bottom());
-            let br_aabb = Aabb::new(
-                aabb.center().x,
-                aabb.right(),
-                aabb.center().y,
-                aabb.bottom(),
-            );
+    pub fn new_with(entries: Vec<Entry>, config: QuadtreeConfig) -> Self {
+        let mut quadtree = Self::new(config);
+        for entry in entries {
+            quadtree.insert(entry.id, entry.aabb);
+        }
+        quadtree
+    }
 
-            let mut tl_entries = vec![];
-            let mut tr_entries = vec![];
-            let mut bl_entries = vec![];
-            let mut br_entries = vec![];
+    pub const fn config(&self) -> &QuadtreeConfig {
+        &self.config
+    }
 
-            for entry in entries.iter() {
-                if entry.aabb.intersects(&tl_aabb) {
-                    tl_entries.push(*entry);
-                }
-                if entry.aabb.intersects(&tr_aabb) {
-                    tr_entries.push(*entry);
-                }
-                if entry.aabb.intersects(&bl_aabb) {
-                    bl_entries.push(*entry);
+    pub const fn root(&self) -> Option<usize> {
+        self.root
+    }
+
+    pub fn node(&self, index: usize) -> &QuadtreeNode {
+        &self.nodes[index]
+    }
+
+    // Upper bound of node indices, including currently unused nodes
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    pub fn loose_bounds(&self, index: usize) -> Aabb {
+        let node = &self.nodes[index];
+        let loose_half_size = node.half_size * self.config.looseness;
+        Aabb::new(
+            node.center.x - loose_half_size,
+            node.center.x + loose_half_size,
+            node.center.y + loose_half_size,
+            node.center.y - loose_half_size,
+        )
+    }
+
+    pub fn insert(&mut self, id: usize, aabb: Aabb) {
+        self.remove(id);
+
+        let root = match self.root {
+            Some(root) => self.grow_to_fit(root, &aabb),
+            None => {
+                let extent = aabb.extent();
+                let half_size = (extent.x.max(extent.y) / (self.config.looseness - 1.0)).max(1.0);
+                let root = self.allocate_node(aabb.center(), half_size, None);
+                self.root = Some(root);
+                root
+            }
+        };
+
+        let mut index = root;
+        let mut depth = 0;
+        loop {
+            if let Some(children) = self.nodes[index].children {
+                let child = children[self.nodes[index].quadrant(&aabb.center())];
+                if self.fits(child, &aabb) {
+                    index = child;
+                    depth += 1;
+                    continue;
                 }
-                if entry.aabb.intersects(&br_aabb) {
-                    br_entries.push(*entry);
+            }
+            break;
+        }
+
+        self.nodes[index].entries.push(Entry { id, aabb });
+        self.locations.insert(id, index);
+
+        if self.nodes[index].children.is_none()
+            && self.nodes[index].entries.len() > self.config.capacity
+            && depth < self.config.max_depth
+        {
+            self.subdivide(index);
+        }
+    }
+
+    pub fn remove(&mut self, id: usize) {
+        let Some(index) = self.locations.remove(&id) else {
+            return;
+        };
+
+        let entries = &mut self.nodes[index].entries;
+        if let Some(position) = entries.iter().position(|e| e.id == id) {
+            entries.swap_remove(position);
+        }
+
+        self.collapse(index);
+    }
+
+    pub fn update(&mut self, id: usize, aabb: Aabb) {
+        if let Some(&index) = self.locations.get(&id) {
+            if self.fits(index, &aabb) {
+                // Loose bounds keep entries in place for small movements
+                let entries = &mut self.nodes[index].entries;
+                if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
+                    entry.aabb = aabb;
+                    return;
                 }
             }
+        }
 
-            let children: [Self; 4] = [
-                Self::new(tl_entries, depth + 1),
-                Self::new(tr_entries, depth + 1),
-                Self::new(bl_entries, depth + 1),
-                Self::new(br_entries, depth + 1),
-            ];
-
-            Self {
-                entries: vec![],
-                aabb,
-                children: Some(Box::new(children)),
+        self.insert(id, aabb);
+    }
+
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        let mut found = vec![];
+        let mut nodes_to_search: Vec<usize> = self.root.into_iter().collect();
+
+        while let Some(index) = nodes_to_search.pop() {
+            if !self.loose_bounds(index).intersects(aabb) {
+                continue;
             }
-        } else {
-            Self {
-                entries: vec![],
-                aabb: Aabb::default(),
-                children: None,
+
+            let node = &self.nodes[index];
+            found.extend(
+                node.entries
+                    .iter()
+                    .filter(|e| e.aabb.intersects(aabb))
+                    .map(|e| e.id),
+            );
+
+            if let Some(children) = node.children {
+                nodes_to_search.extend(children);
             }
         }
+
+        found.sort_unstable();
+        found
     }
 
-    pub fn collect_collisions(&self, entry: &Entry, collisions: &mut Vec<Entry>) {
-        if !entry.aabb.intersects(&self.aabb) {
-            return;
+    // Loose bounds let entries of siblings and cousins overlap, so every entry searches all
+    // nodes its box reaches. Pairs are kept from the entry with the lower id only
+    pub fn internal_collisions(&self) -> Vec<(usize, usize)> {
+        let mut collisions = vec![];
+        let mut nodes_to_search = vec![];
+
+        for lhs in self.nodes.iter().flat_map(|node| &node.entries) {
+            nodes_to_search.extend(self.root);
+            while let Some(index) = nodes_to_search.pop() {
+                if !self.loose_bounds(index).intersects(&lhs.aabb) {
+                    continue;
+                }
+
+                let node = &self.nodes[index];
+                for rhs in &node.entries {
+                    if lhs.id < rhs.id && lhs.aabb.intersects(&rhs.aabb) {
+                        collisions.push((lhs.id, rhs.id));
+                    }
+                }
+                nodes_to_search.extend(node.children.into_iter().flatten());
+            }
         }
 
-        if let Some(children) = &self.children {
-            children[0].collect_collisions(entry, collisions);
-            children[1].collect_collisions(entry, collisions);
-            children[2].collect_collisions(entry, collisions);
-            children[3].collect_collisions(entry, collisions);
-        } else {
-            collisions.extend(
-                self.entries
-                    .iter()
-                    .filter(|e| e.aabb.intersects(&entry.aabb)),
+        canonicalize_pairs(&mut collisions);
+        collisions
+    }
+
+    fn fits(&self, index: usize, aabb: &Aabb) -> bool {
+        let node = &self.nodes[index];
+        let extent = aabb.extent();
+        let max_half_extent = extent.x.max(extent.y) / 2.0;
+
+        node.contains_point(&aabb.center())
+            && max_half_extent <= node.half_size * (self.config.looseness - 1.0)
+    }
+
+    // Doubles the root until the entry fits, the old root becoming one of the new quadrants
+    fn grow_to_fit(&mut self, mut root: usize, aabb: &Aabb) -> usize {
+        let target = aabb.center();
+
+        for _ in 0..MAX_ROOT_GROWTH {
+            if self.fits(root, aabb) {
+                break;
+            }
+
+            let old_center = self.nodes[root].center;
+            let half_size = self.nodes[root].half_size;
+            let direction = Vec2::new_at(
+                if target.x >= old_center.x { 1.0 } else { -1.0 },
+                if target.y >= old_center.y { 1.0 } else { -1.0 },
             );
+
+            let new_root = self.allocate_node(old_center + direction * half_size, half_size * 2.0, None);
+            let children = self.create_children(new_root);
+            let old_quadrant = self.nodes[new_root].quadrant(&old_center);
+
+            self.free_node(children[old_quadrant]);
+            let mut children = children;
+            children[old_quadrant] = root;
+            self.nodes[new_root].children = Some(children);
+            self.nodes[root].parent = Some(new_root);
+
+            root = new_root;
         }
+
+        self.root = Some(root);
+        root
     }
-}
 
-impl<const S: usize> Quadtree<S> {
-    pub fn new(entries: Vec<Entry>) -> Self {
-        Self {
-            root: Box::new(Node::new(entries, 0)),
+    fn create_children(&mut self, index: usize) -> [usize; 4] {
+        let center = self.nodes[index].center;
+        let half_size = self.nodes[index].half_size / 2.0;
+
+        let offsets = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)];
+        offsets.map(|(x, y)| {
+            self.allocate_node(center + Vec2::new_at(x, y) * half_size, half_size, Some(index))
+        })
+    }
+
+    fn subdivide(&mut self, index: usize) {
+        let children = self.create_children(index);
+        self.nodes[index].children = Some(children);
+
+        let entries = std::mem::take(&mut self.nodes[index].entries);
+        for entry in entries {
+            let child = children[self.nodes[index].quadrant(&entry.aabb.center())];
+            let target = if self.fits(child, &entry.aabb) { child } else { index };
+
+            self.nodes[target].entries.push(entry);
+            self.locations.insert(entry.id, target);
+        }
+    }
+
+    // Merges children back into their parent once they hold few enough entries
+    fn collapse(&mut self, mut index: usize) {
+        loop {
+            if let Some(children) = self.nodes[index].children {
+                let all_leaves = children.iter().all(|c| self.nodes[*c].children.is_none());
+                let count = self.nodes[index].entries.len()
+                    + children.iter().map(|c| self.nodes[*c].entries.len()).sum::<usize>();
+
+                if !all_leaves || count > self.config.capacity {
+                    return;
+                }
+
+                for child in children {
+                    let entries = std::mem::take(&mut self.nodes[child].entries);
+                    for entry in &entries {
+                        self.locations.insert(entry.id, index);
+                    }
+                    self.nodes[index].entries.extend(entries);
+                    self.free_node(child);
+                }
+                self.nodes[index].children = None;
+            }
+
+            match self.nodes[index].parent {
+                Some(parent) => index = parent,
+                None => return,
+            }
         }
     }
 
-    pub fn collect_collisions(&self, entry: &Entry, collisions: &mut Vec<Entry>) {
-        self.root.collect_collisions(entry, collisions);
+    fn allocate_node(&mut self, center: Vec2, half_size: f64, parent: Option<usize>) -> usize {
+        let node = QuadtreeNode {
+            center,
+            half_size,
+            parent,
+            entries: vec![],
+            children: None,
+        };
+
+        if let Some(index) = self.free_nodes.pop() {
+            self.nodes[index] = node;
+            return index;
+        }
+
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    fn free_node(&mut self, index: usize) {
+        self.nodes[index] = QuadtreeNode::default();
+        self.free_nodes.push(index);
+    }
+}
+
+impl BroadPhase for Quadtree {
+    fn insert(&mut self, id: usize, aabb: Aabb) {
+        Quadtree::insert(self, id, aabb);
+    }
+
+    fn remove(&mut self, id: usize) {
+        Quadtree::remove(self, id);
+    }
+
+    fn update(&mut self, id: usize, aabb: Aabb) {
+        Quadtree::update(self, id, aabb);
+    }
+
+    fn candidate_pairs(&mut self) -> Vec<(usize, usize)> {
+        self.internal_collisions()
+    }
+
+    fn query_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        Quadtree::query_aabb(self, aabb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grow_and_collapse() {
+        let config = QuadtreeConfig {
+            capacity: 2,
+            max_depth: 6,
+            looseness: 2.0,
+        };
+        let mut quadtree = Quadtree::new(config);
+
+        for id in 0..32 {
+            let x = (id % 8) as f64 * 10.0;
+            let y = (id / 8) as f64 * 10.0;
+            quadtree.insert(id, Aabb::new(x, x + 4.0, y + 4.0, y));
+        }
+        quadtree.insert(100, Aabb::new(-5000.0, -4990.0, 10.0, 0.0));
+
+        assert_eq!(33, quadtree.len());
+        assert_eq!(vec![9, 10], quadtree.query_aabb(&Aabb::new(12.0, 21.0, 12.0, 11.0)));
+        assert!(quadtree.internal_collisions().is_empty());
+
+        quadtree.update(1, Aabb::new(2.0, 6.0, 4.0, 0.0));
+        assert_eq!(vec![(0, 1)], quadtree.internal_collisions());
+
+        for id in 0..32 {
+            quadtree.remove(id);
+        }
+        assert_eq!(vec![100], quadtree.query_aabb(&Aabb::new(-1.0e4, 1.0e4, 1.0e4, -1.0e4)));
+        let root = quadtree.node(quadtree.root().unwrap());
+        assert!(root.children().is_none());
     }
 }
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use super::{
+    aabb::Aabb,
+    broad_phase::{canonicalize_pairs, BroadPhase},
+};
+
+const DEFAULT_CELL_SIZE: f64 = 32.0;
+// Entries covering more cells than this are tested against everything instead
+const MAX_CELLS_PER_ENTRY: i64 = 64;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct CellRange {
+    min_x: i64,
+    max_x: i64,
+    min_y: i64,
+    max_y: i64,
+}
+
+impl CellRange {
+    fn cell_count(&self) -> i64 {
+        let width = self.max_x.saturating_sub(self.min_x).saturating_add(1);
+        let height = self.max_y.saturating_sub(self.min_y).saturating_add(1);
+        width.saturating_mul(height)
+    }
+
+    fn cells(&self) -> impl Iterator<Item = (i64, i64)> + '_ {
+        (self.min_x..=self.max_x).flat_map(move |x| (self.min_y..=self.max_y).map(move |y| (x, y)))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct HashEntry {
+    aabb: Aabb,
+    // `None` for oversized entries
+    range: Option<CellRange>,
+}
+
+// Unbounded uniform grid, cells are only allocated where entries are
+#[derive(Clone)]
+pub struct SpatialHash {
+    cell_size: f64,
+    cells: HashMap<(i64, i64), Vec<usize>>,
+    entries: HashMap<usize, HashEntry>,
+    oversized: Vec<usize>,
+}
+
+impl Default for SpatialHash {
+    fn default() -> Self {
+        Self::new(DEFAULT_CELL_SIZE)
+    }
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: f64) -> Self {
+        assert!(cell_size > 0.0, "Cell size must be positive!");
+
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+            entries: HashMap::new(),
+            oversized: vec![],
+        }
+    }
+
+    pub const fn cell_size(&self) -> f64 {
+        self.cell_size
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn cell_coordinate(&self, value: f64) -> i64 {
+        (value / self.cell_size).floor() as i64
+    }
+
+    fn cell_range(&self, aabb: &Aabb) -> CellRange {
+        CellRange {
+            min_x: self.cell_coordinate(aabb.left()),
+            max_x: self.cell_coordinate(aabb.right()),
+            min_y: self.cell_coordinate(aabb.bottom()),
+            max_y: self.cell_coordinate(aabb.top()),
+        }
+    }
+
+    fn link(&mut self, id: usize, range: &Option<CellRange>) {
+        match range {
+            Some(range) => {
+                for cell in range.cells() {
+                    self.cells.entry(cell).or_default().push(id);
+                }
+            }
+            None => self.oversized.push(id),
+        }
+    }
+
+    fn unlink(&mut self, id: usize, range: &Option<CellRange>) {
+        match range {
+            Some(range) => {
+                for cell in range.cells() {
+                    if let Some(ids) = self.cells.get_mut(&cell) {
+                        if let Some(index) = ids.iter().position(|other| *other == id) {
+                            ids.swap_remove(index);
+                        }
+                        if ids.is_empty() {
+                            self.cells.remove(&cell);
+                        }
+                    }
+                }
+            }
+            None => self.oversized.retain(|other| *other != id),
+        }
+    }
+
+    fn range_for(&self, aabb: &Aabb) -> Option<CellRange> {
+        let range = self.cell_range(aabb);
+        if range.cell_count() > MAX_CELLS_PER_ENTRY {
+            None
+        } else {
+            Some(range)
+        }
+    }
+}
+
+impl BroadPhase for SpatialHash {
+    fn insert(&mut self, id: usize, aabb: Aabb) {
+        self.remove(id);
+
+        let range = self.range_for(&aabb);
+        self.link(id, &range);
+        self.entries.insert(id, HashEntry { aabb, range });
+    }
+
+    fn remove(&mut self, id: usize) {
+        if let Some(entry) = self.entries.remove(&id) {
+            self.unlink(id, &entry.range);
+        }
+    }
+
+    fn update(&mut self, id: usize, aabb: Aabb) {
+        let range = self.range_for(&aabb);
+        match self.entries.get_mut(&id) {
+            // Still covering the same cells, nothing to relink
+            Some(entry) if entry.range == range => entry.aabb = aabb,
+            _ => self.insert(id, aabb),
+        }
+    }
+
+    fn candidate_pairs(&mut self) -> Vec<(usize, usize)> {
+        let mut collisions = vec![];
+
+        for ids in self.cells.values() {
+            for (index, lhs) in ids.iter().enumerate() {
+                let lhs_aabb = &self.entries[lhs].aabb;
+                for rhs in &ids[index + 1..] {
+                    if lhs_aabb.intersects(&self.entries[rhs].aabb) {
+                        collisions.push((*lhs, *rhs));
+                    }
+                }
+            }
+        }
+
+        for lhs in &self.oversized {
+            let lhs_aabb = &self.entries[lhs].aabb;
+            for (rhs, rhs_entry) in &self.entries {
+                if lhs != rhs && lhs_aabb.intersects(&rhs_entry.aabb) {
+                    collisions.push((*lhs, *rhs));
+                }
+            }
+        }
+
+        canonicalize_pairs(&mut collisions);
+        collisions
+    }
+
+    fn query_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        let range = self.cell_range(aabb);
+
+        let mut found: Vec<usize> = if range.cell_count() as usize > self.cells.len() {
+            // Cheaper to look at every occupied cell than at every covered one
+            self.cells.values().flatten().copied().collect()
+        } else {
+            range
+                .cells()
+                .filter_map(|cell| self.cells.get(&cell))
+                .flatten()
+                .copied()
+                .collect()
+        };
+        found.extend(&self.oversized);
+
+        found.retain(|id| self.entries[id].aabb.intersects(aabb));
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unbounded_and_oversized() {
+        let mut hash = SpatialHash::new(10.0);
+        hash.insert(0, Aabb::new(-1.0e6, -1.0e6 + 5.0, 5.0, 0.0));
+        hash.insert(1, Aabb::new(-1.0e6 + 3.0, -1.0e6 + 8.0, 5.0, 0.0));
+        hash.insert(2, Aabb::new(-2.0e6, 2.0e6, 1.0, -1.0));
+        hash.insert(3, Aabb::new(500.0, 505.0, 505.0, 500.0));
+
+        assert_eq!(vec![(0, 1), (0, 2), (1, 2)], hash.candidate_pairs());
+
+        hash.update(3, Aabb::new(500.0, 505.0, 2.0, -3.0));
+        assert_eq!(vec![(0, 1), (0, 2), (1, 2), (2, 3)], hash.candidate_pairs());
+        assert_eq!(vec![2, 3], hash.query_aabb(&Aabb::new(499.0, 501.0, 0.0, -1.0)));
+
+        hash.remove(2);
+        assert_eq!(vec![(0, 1)], hash.candidate_pairs());
+    }
+}
@@ -0,0 +1,93 @@
+use super::{
+    aabb::Aabb,
+    broad_phase::{canonicalize_pairs, BroadPhase},
+    entry::EntrySet,
+};
+
+// Sort and sweep along the x axis. The order of the previous frame is kept,
+// so for coherent motion the insertion sort is close to linear
+#[derive(Clone, Default)]
+pub struct SweepAndPrune {
+    entries: EntrySet,
+    order: Vec<usize>,
+}
+
+impl SweepAndPrune {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn sort(&mut self) {
+        let entries = &self.entries;
+        self.order.retain(|id| entries.get(*id).is_some());
+
+        let left = |id: usize| entries.get(id).map_or(f64::MAX, |e| e.aabb.left());
+        for index in 1..self.order.len() {
+            let mut position = index;
+            while position > 0 && left(self.order[position - 1]) > left(self.order[position]) {
+                self.order.swap(position - 1, position);
+                position -= 1;
+            }
+        }
+    }
+}
+
+impl BroadPhase for SweepAndPrune {
+    fn insert(&mut self, id: usize, aabb: Aabb) {
+        if self.entries.get(id).is_none() {
+            self.order.push(id);
+        }
+        self.entries.insert(id, aabb);
+    }
+
+    fn remove(&mut self, id: usize) {
+        // Otherwise a reused slot would be swept twice and paired with itself
+        self.order.retain(|other| *other != id);
+        self.entries.remove(id);
+    }
+
+    fn update(&mut self, id: usize, aabb: Aabb) {
+        self.insert(id, aabb);
+    }
+
+    fn candidate_pairs(&mut self) -> Vec<(usize, usize)> {
+        self.sort();
+
+        let mut collisions = vec![];
+        let mut active: Vec<Aabb> = vec![];
+        let mut active_ids: Vec<usize> = vec![];
+
+        for id in &self.order {
+            let aabb = self.entries.get(*id).unwrap().aabb;
+
+            let mut index = 0;
+            while index < active.len() {
+                if active[index].right() < aabb.left() {
+                    active.swap_remove(index);
+                    active_ids.swap_remove(index);
+                    continue;
+                }
+
+                if active[index].intersects(&aabb) {
+                    collisions.push((active_ids[index], *id));
+                }
+                index += 1;
+            }
+
+            active.push(aabb);
+            active_ids.push(*id);
+        }
+
+        canonicalize_pairs(&mut collisions);
+        collisions
+    }
+
+    fn query_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        self.entries
+            .entries()
+            .iter()
+            .filter(|e| e.aabb.intersects(aabb))
+            .map(|e| e.id)
+            .collect()
+    }
+}
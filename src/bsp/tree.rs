@@ -1,8 +1,15 @@
+use crate::primitive::Vec2;
+
 use super::{
+    aabb::Aabb,
+    broad_phase::StaticBroadPhase,
     entry::Entry,
     node::Node,
     partition::{Bucket, Dimension},
 };
+use super::broad_phase::canonicalize_pairs;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 
 pub struct Tree {
     entries: Vec<Entry>,
@@ -17,52 +24,172 @@ impl Tree {
         }
     }
 
+    #[cfg(not(feature = "parallel"))]
     pub fn internal_collisions(&self) -> Vec<(usize, usize)> {
         let mut collisions: Vec<(usize, usize)> = vec![];
-        let mut nodes_to_search = vec![];
         for entry in &self.entries {
-            nodes_to_search.clear();
-            nodes_to_search.push(&self.root);
+            self.entry_collisions(entry, &mut collisions);
+        }
+
+        canonicalize_pairs(&mut collisions);
+        collisions
+    }
+
+    #[cfg(feature = "parallel")]
+    pub fn internal_collisions(&self) -> Vec<(usize, usize)> {
+        let mut collisions: Vec<(usize, usize)> = self
+            .entries
+            .par_iter()
+            .fold(Vec::new, |mut collisions, entry| {
+                self.entry_collisions(entry, &mut collisions);
+                collisions
+            })
+            .flatten_iter()
+            .collect();
 
-            while let Some(node) = nodes_to_search.pop() {
-                let mut check_node_entries = true;
+        canonicalize_pairs(&mut collisions);
+        collisions
+    }
+
+    fn entry_collisions(&self, entry: &Entry, collisions: &mut Vec<(usize, usize)>) {
+        let mut nodes_to_search = vec![&self.root];
+
+        while let Some(node) = nodes_to_search.pop() {
+            let mut check_node_entries = true;
+
+            if let Some(node_aabb) = node.entries_aabb {
+                check_node_entries = entry.aabb.intersects(&node_aabb);
+            }
+
+            if check_node_entries {
+                for other_entry in &node.entries {
+                    // Every pair is found from both sides, only keep one of them
+                    if other_entry.id <= entry.id {
+                        continue;
+                    }
 
-                if let Some(node_aabb) = node.entries_aabb {
-                    check_node_entries = entry.aabb.intersects(&node_aabb);
+                    if entry.aabb.intersects(&other_entry.aabb) {
+                        collisions.push((entry.id, other_entry.id));
+                    }
                 }
+            }
 
-                if check_node_entries {
-                    for other_entry in &node.entries {
-                        if other_entry.id == entry.id {
-                            continue;
+            if let Some(children) = &node.children {
+                if let Some(partition) = &node.partition {
+                    let partition_bucket = partition.classify(&entry.aabb);
+                    match partition_bucket {
+                        Bucket::Below => {
+                            nodes_to_search.push(&children.left_child);
                         }
-
-                        if entry.aabb.intersects(&other_entry.aabb) {
-                            collisions.push((entry.id, other_entry.id));
+                        Bucket::Intersecting => {
+                            nodes_to_search.push(&children.left_child);
+                            nodes_to_search.push(&children.right_child);
+                        }
+                        Bucket::Above => {
+                            nodes_to_search.push(&children.right_child);
                         }
                     }
                 }
+            }
+        }
+    }
 
-                if let Some(children) = &node.children {
-                    if let Some(partition) = &node.partition {
-                        let partition_bucket = partition.classify(&entry.aabb);
-                        match partition_bucket {
-                            Bucket::Below => {
-                                nodes_to_search.push(&children.left_child);
-                            }
-                            Bucket::Intersecting => {
-                                nodes_to_search.push(&children.left_child);
-                                nodes_to_search.push(&children.right_child);
-                            }
-                            Bucket::Above => {
-                                nodes_to_search.push(&children.right_child);
-                            }
+    pub fn ray_candidates(&self, origin: &Vec2, dir: &Vec2, max_toi: f64) -> Vec<(usize, f64)> {
+        let mut candidates: Vec<(usize, f64)> = vec![];
+        let end = *origin + *dir * max_toi;
+        let mut nodes_to_search = vec![&self.root];
+
+        while let Some(node) = nodes_to_search.pop() {
+            let mut check_node_entries = true;
+
+            if let Some(node_aabb) = node.entries_aabb {
+                check_node_entries = node_aabb.ray_intersection(origin, dir, max_toi).is_some();
+            }
+
+            if check_node_entries {
+                for entry in &node.entries {
+                    if let Some(toi) = entry.aabb.ray_intersection(origin, dir, max_toi) {
+                        candidates.push((entry.id, toi));
+                    }
+                }
+            }
+
+            if let Some(children) = &node.children {
+                if let Some(partition) = &node.partition {
+                    let (min, max) = match partition.dimension {
+                        Dimension::X => (origin.x.min(end.x), origin.x.max(end.x)),
+                        Dimension::Y => (origin.y.min(end.y), origin.y.max(end.y)),
+                    };
+
+                    if min <= partition.value {
+                        nodes_to_search.push(&children.left_child);
+                    }
+                    if max >= partition.value {
+                        nodes_to_search.push(&children.right_child);
+                    }
+                }
+            }
+        }
+
+        candidates
+    }
+
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        let mut found: Vec<usize> = vec![];
+        let mut nodes_to_search = vec![&self.root];
+
+        while let Some(node) = nodes_to_search.pop() {
+            let mut check_node_entries = true;
+
+            if let Some(node_aabb) = node.entries_aabb {
+                check_node_entries = aabb.intersects(&node_aabb);
+            }
+
+            if check_node_entries {
+                found.extend(
+                    node.entries
+                        .iter()
+                        .filter(|e| e.aabb.intersects(aabb))
+                        .map(|e| e.id),
+                );
+            }
+
+            if let Some(children) = &node.children {
+                if let Some(partition) = &node.partition {
+                    match partition.classify(aabb) {
+                        Bucket::Below => {
+                            nodes_to_search.push(&children.left_child);
+                        }
+                        Bucket::Intersecting => {
+                            nodes_to_search.push(&children.left_child);
+                            nodes_to_search.push(&children.right_child);
+                        }
+                        Bucket::Above => {
+                            nodes_to_search.push(&children.right_child);
                         }
                     }
                 }
             }
         }
 
-        collisions
+        found
+    }
+}
+
+impl StaticBroadPhase for Tree {
+    fn build(entries: Vec<Entry>) -> Self {
+        Self::new_with(entries)
+    }
+
+    fn collect_pairs(&self) -> Vec<(usize, usize)> {
+        self.internal_collisions()
+    }
+
+    fn query_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        Tree::query_aabb(self, aabb)
+    }
+
+    fn ray_candidates(&self, origin: &Vec2, dir: &Vec2, max_toi: f64) -> Vec<(usize, f64)> {
+        Tree::ray_candidates(self, origin, dir, max_toi)
     }
 }
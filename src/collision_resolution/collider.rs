@@ -9,6 +9,7 @@ pub struct CircleCollider<'a> {
 pub struct SquareCollider<'a> {
     pub position: &'a mut Vec2,
     pub edge_length: f64,
+    pub angle: f64,
     pub mass: Mass,
 }
 
@@ -27,9 +28,11 @@ pub fn get_as_circle_collider(body: &mut Body) -> Option<CircleCollider> {
 pub fn get_as_square_collider(body: &mut Body) -> Option<SquareCollider> {
     if let Shape::Square { edge_length } = *body.get_shape() {
         let mass = *body.get_mass();
+        let angle = body.angle;
         return Some(SquareCollider {
             position: &mut body.position,
             edge_length,
+            angle,
             mass,
         });
     }
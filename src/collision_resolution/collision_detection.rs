@@ -1,29 +1,77 @@
 
-use crate::primitive::Vec2;
+use crate::{math, primitive::Vec2};
 
 use super::collider::{CircleCollider, SquareCollider};
+use super::distance::nearest_point_on_square;
 use super::collision_event::CollisionEvent;
 
 fn dist_to_square_surface(angle: f64, edge_length: f64) -> f64 {
     let half_edge_len = edge_length / 2.0;
-    let short_edge_len = half_edge_len / angle.cos();
+    let short_edge_len = half_edge_len / math::cos(angle);
 
-    short_edge_len.hypot(half_edge_len)
+    math::hypot(short_edge_len, half_edge_len)
 }
 
-fn nearest_point_to(square: &SquareCollider, point: &Vec2) -> Vec2 {
-    let mut pos_in_square = *point;
+// Edge directions of the square in world space
+fn square_axes(square: &SquareCollider) -> [Vec2; 2] {
+    let axis = Vec2::new_polar(square.angle, 1.0);
+    [axis, axis.perpendicular()]
+}
 
-    let half_edge_length = square.edge_length / 2.0;
-    let edge_min_x = square.position.x - half_edge_length;
-    let edge_max_x = square.position.x + half_edge_length;
-    let edge_min_y = square.position.y - half_edge_length;
-    let edge_max_y = square.position.y + half_edge_length;
+fn projected_half_extent(square: &SquareCollider, axis: &Vec2) -> f64 {
+    let [first, second] = square_axes(square);
+    let reach = axis.scalar_product(&first).abs() + axis.scalar_product(&second).abs();
+    square.edge_length / 2.0 * reach
+}
 
-    pos_in_square.x = pos_in_square.x.clamp(edge_min_x, edge_max_x);
-    pos_in_square.y = pos_in_square.y.clamp(edge_min_y, edge_max_y);
+// Corner of the square reaching furthest along `direction`
+fn support_point(square: &SquareCollider, direction: &Vec2) -> Vec2 {
+    let half_edge_len = square.edge_length / 2.0;
+    square_axes(square)
+        .iter()
+        .fold(*square.position, |corner, axis| {
+            corner + axis.scaled(half_edge_len.copysign(direction.scalar_product(axis)))
+        })
+}
+
+// Separating axis test over the edge directions of both squares
+fn detect_rotated_square_vs_square_collision(
+    lhs: &SquareCollider,
+    rhs: &SquareCollider,
+) -> Option<CollisionEvent> {
+    let position_delta = *rhs.position - *lhs.position;
+    let [lhs_axes, rhs_axes] = [square_axes(lhs), square_axes(rhs)];
+
+    let mut penetration_depth = f64::INFINITY;
+    let mut contact_normal = Vec2::default();
+    let mut on_lhs_face = true;
+    for (index, axis) in lhs_axes.iter().chain(&rhs_axes).enumerate() {
+        let distance = position_delta.scalar_product(axis);
+        let overlap =
+            projected_half_extent(lhs, axis) + projected_half_extent(rhs, axis) - distance.abs();
+        if overlap < 0.0 {
+            return None;
+        }
+
+        if overlap < penetration_depth {
+            penetration_depth = overlap;
+            contact_normal = if distance > 0.0 { -*axis } else { *axis };
+            on_lhs_face = index < lhs_axes.len();
+        }
+    }
 
-    pos_in_square
+    // The corner reaching into the face of the other square, moved halfway out of it
+    let contact_point = if on_lhs_face {
+        support_point(rhs, &contact_normal) - contact_normal.scaled(penetration_depth / 2.0)
+    } else {
+        support_point(lhs, &-contact_normal) + contact_normal.scaled(penetration_depth / 2.0)
+    };
+
+    Some(CollisionEvent {
+        contact: contact_point,
+        normal: contact_normal,
+        penetration_depth,
+    })
 }
 
 pub fn detect_circle_vs_circle_collision(
@@ -33,7 +81,7 @@ pub fn detect_circle_vs_circle_collision(
     let pos_delta = *rhs.position - *lhs.position;
     let distance_squared = pos_delta.len_squared();
     let min_distance = lhs.radius + rhs.radius;
-    let min_distance_squared = min_distance.powi(2);
+    let min_distance_squared = min_distance * min_distance;
 
     if distance_squared > min_distance_squared {
         return None;
@@ -60,6 +108,10 @@ pub fn detect_square_vs_square_collision(
     lhs: &SquareCollider,
     rhs: &SquareCollider,
 ) -> Option<CollisionEvent> {
+    if lhs.angle != 0.0 || rhs.angle != 0.0 {
+        return detect_rotated_square_vs_square_collision(lhs, rhs);
+    }
+
     let min_distance = (lhs.edge_length + rhs.edge_length) / 2.0;
     let position_delta = *rhs.position - *lhs.position;
     let overlap_x = min_distance - position_delta.x.abs();
@@ -109,19 +161,20 @@ pub fn detect_circle_vs_square_collision(
     lhs: &CircleCollider,
     rhs: &SquareCollider,
 ) -> Option<CollisionEvent> {
-    let nearest_point = nearest_point_to(rhs, lhs.position);
+    let nearest_point =
+        nearest_point_on_square(rhs.position, rhs.edge_length, rhs.angle, lhs.position);
     let mut diff_to_nearest = nearest_point - *lhs.position;
     let mut dist_to_nearest_squared = diff_to_nearest.len_squared();
 
     if dist_to_nearest_squared == 0.0
     {
         // @todo: this is a really dirty workaround if the center of a circle is inside the square
-        // as on those cases, nearest_point_to will just return the circle center
+        // as on those cases, nearest_point_on_square will just return the circle center
         dist_to_nearest_squared = 0.0001;
         diff_to_nearest = *rhs.position - *lhs.position;
     }
 
-    let radius_squared = lhs.radius.powi(2);
+    let radius_squared = lhs.radius * lhs.radius;
     if radius_squared < dist_to_nearest_squared {
         return None;
     }
@@ -0,0 +1,431 @@
+use crate::primitive::{Body, Shape, Vec2};
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ClosestPoints {
+    // Negative when the bodies are penetrating
+    pub distance: f64,
+    pub points: [Vec2; 2],
+    // Points from the first body towards the second one
+    pub normal: Vec2,
+}
+
+// The point itself when it lies inside the square
+pub fn nearest_point_on_square(center: &Vec2, edge_length: f64, angle: f64, point: &Vec2) -> Vec2 {
+    let half_edge_length = edge_length / 2.0;
+    if angle == 0.0 {
+        return Vec2::new_at(
+            point.x.clamp(center.x - half_edge_length, center.x + half_edge_length),
+            point.y.clamp(center.y - half_edge_length, center.y + half_edge_length),
+        );
+    }
+
+    // Clamped in the frame of the square, where its edges are axis aligned
+    let local = (*point - *center).rotated(-angle);
+    let nearest = nearest_point_on_square(&Vec2::new(), edge_length, 0.0, &local);
+    if nearest == local {
+        // Rotating back could miss the point by a rounding error
+        return *point;
+    }
+    *center + nearest.rotated(angle)
+}
+
+pub fn nearest_point_on_body(body: &Body, point: &Vec2) -> Vec2 {
+    match *body.get_shape() {
+        Shape::Circle { radius } => {
+            let mut delta = *point - body.position;
+            if delta.len_squared() <= radius * radius {
+                return *point;
+            }
+            delta.set_len(radius);
+            body.position + delta
+        }
+        Shape::Square { edge_length } => {
+            nearest_point_on_square(&body.position, edge_length, body.angle, point)
+        }
+    }
+}
+
+// Moves a result computed in the frame of a body at `origin`, turned by `angle`, back into
+// world space
+fn to_world(result: ClosestPoints, origin: &Vec2, angle: f64) -> ClosestPoints {
+    ClosestPoints {
+        distance: result.distance,
+        points: result.points.map(|point| *origin + point.rotated(angle)),
+        normal: result.normal.rotated(angle),
+    }
+}
+
+fn direction_or_default(delta: Vec2) -> Vec2 {
+    if delta.len_squared() == 0.0 {
+        return Vec2::new_at(1.0, 0.0);
+    }
+
+    let mut direction = delta;
+    direction.normalize();
+    direction
+}
+
+fn circle_vs_circle(lhs: &Vec2, lhs_radius: f64, rhs: &Vec2, rhs_radius: f64) -> ClosestPoints {
+    let delta = *rhs - *lhs;
+    let normal = direction_or_default(delta);
+
+    ClosestPoints {
+        distance: delta.len() - lhs_radius - rhs_radius,
+        points: [*lhs + normal * lhs_radius, *rhs - normal * rhs_radius],
+        normal,
+    }
+}
+
+fn circle_vs_square(
+    center: &Vec2,
+    radius: f64,
+    square: &Vec2,
+    edge_length: f64,
+    angle: f64,
+) -> ClosestPoints {
+    let nearest_point = nearest_point_on_square(square, edge_length, angle, center);
+
+    if nearest_point != *center {
+        let delta = nearest_point - *center;
+        let normal = direction_or_default(delta);
+
+        return ClosestPoints {
+            distance: delta.len() - radius,
+            points: [*center + normal * radius, nearest_point],
+            normal,
+        };
+    }
+
+    // The circle center is inside the square, so push it out through the nearest face,
+    // which is picked in the frame of the square
+    let half_edge_length = edge_length / 2.0;
+    let local = (*center - *square).rotated(-angle);
+    let depth_x = half_edge_length - local.x.abs();
+    let depth_y = half_edge_length - local.y.abs();
+
+    let (face_depth, outward) = if depth_x < depth_y {
+        (depth_x, Vec2::new_at(local.x.signum(), 0.0))
+    } else {
+        (depth_y, Vec2::new_at(0.0, local.y.signum()))
+    };
+    let outward = outward.rotated(angle);
+
+    let normal = -outward;
+    ClosestPoints {
+        distance: -(face_depth + radius),
+        points: [*center + normal * radius, *center + outward * face_depth],
+        normal,
+    }
+}
+
+// Per axis, returns the closest coordinates on both intervals and the signed gap between them
+fn interval_closest(lhs: f64, lhs_half: f64, rhs: f64, rhs_half: f64) -> (f64, f64, f64) {
+    let gap = (rhs - lhs).abs() - lhs_half - rhs_half;
+    if gap > 0.0 {
+        let direction = (rhs - lhs).signum();
+        return (lhs + direction * lhs_half, rhs - direction * rhs_half, gap);
+    }
+
+    let overlap_min = (lhs - lhs_half).max(rhs - rhs_half);
+    let overlap_max = (lhs + lhs_half).min(rhs + rhs_half);
+    let mid = (overlap_min + overlap_max) / 2.0;
+    (mid, mid, gap)
+}
+
+fn aligned_square_vs_square(lhs: &Vec2, lhs_edge: f64, rhs: &Vec2, rhs_edge: f64) -> ClosestPoints {
+    let (lhs_half, rhs_half) = (lhs_edge / 2.0, rhs_edge / 2.0);
+    let (lhs_x, rhs_x, gap_x) = interval_closest(lhs.x, lhs_half, rhs.x, rhs_half);
+    let (lhs_y, rhs_y, gap_y) = interval_closest(lhs.y, lhs_half, rhs.y, rhs_half);
+
+    if gap_x > 0.0 || gap_y > 0.0 {
+        let points = [Vec2::new_at(lhs_x, lhs_y), Vec2::new_at(rhs_x, rhs_y)];
+        return ClosestPoints {
+            distance: Vec2::new_at(gap_x.max(0.0), gap_y.max(0.0)).len(),
+            points,
+            normal: direction_or_default(points[1] - points[0]),
+        };
+    }
+
+    // Penetrating, separate along the axis of least overlap
+    let delta = *rhs - *lhs;
+    if gap_x > gap_y {
+        let direction = if delta.x < 0.0 { -1.0 } else { 1.0 };
+        ClosestPoints {
+            distance: gap_x,
+            points: [
+                Vec2::new_at(lhs.x + direction * lhs_half, lhs_y),
+                Vec2::new_at(rhs.x - direction * rhs_half, rhs_y),
+            ],
+            normal: Vec2::new_at(direction, 0.0),
+        }
+    } else {
+        let direction = if delta.y < 0.0 { -1.0 } else { 1.0 };
+        ClosestPoints {
+            distance: gap_y,
+            points: [
+                Vec2::new_at(lhs_x, lhs.y + direction * lhs_half),
+                Vec2::new_at(rhs_x, rhs.y - direction * rhs_half),
+            ],
+            normal: Vec2::new_at(0.0, direction),
+        }
+    }
+}
+
+fn square_corners(center: &Vec2, edge_length: f64, angle: f64) -> [Vec2; 4] {
+    let half_edge_length = edge_length / 2.0;
+    [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+        .map(|(x, y)| *center + Vec2::new_at(x, y).scaled(half_edge_length).rotated(angle))
+}
+
+fn projected_interval(corners: &[Vec2; 4], axis: &Vec2) -> (f64, f64) {
+    corners.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), corner| {
+        let projection = corner.scalar_product(axis);
+        (min.min(projection), max.max(projection))
+    })
+}
+
+// Corner reaching furthest along `direction`
+fn support_corner(corners: &[Vec2; 4], direction: &Vec2) -> Vec2 {
+    corners
+        .iter()
+        .copied()
+        .max_by(|lhs, rhs| lhs.scalar_product(direction).total_cmp(&rhs.scalar_product(direction)))
+        .unwrap()
+}
+
+// Squares at different angles. Separated ones are closest between a corner of one and the
+// other square, penetrating ones are pushed apart along the axis of least overlap
+fn rotated_square_vs_square(
+    lhs: &Vec2,
+    lhs_edge: f64,
+    lhs_angle: f64,
+    rhs: &Vec2,
+    rhs_edge: f64,
+    rhs_angle: f64,
+) -> ClosestPoints {
+    let lhs_corners = square_corners(lhs, lhs_edge, lhs_angle);
+    let rhs_corners = square_corners(rhs, rhs_edge, rhs_angle);
+    let lhs_axis = Vec2::new_polar(lhs_angle, 1.0);
+    let rhs_axis = Vec2::new_polar(rhs_angle, 1.0);
+    let axes = [lhs_axis, lhs_axis.perpendicular(), rhs_axis, rhs_axis.perpendicular()];
+
+    let mut least_overlap = f64::INFINITY;
+    let mut separating_axis = 0;
+    for (index, axis) in axes.iter().enumerate() {
+        let (lhs_min, lhs_max) = projected_interval(&lhs_corners, axis);
+        let (rhs_min, rhs_max) = projected_interval(&rhs_corners, axis);
+        let overlap = lhs_max.min(rhs_max) - lhs_min.max(rhs_min);
+        if overlap < least_overlap {
+            least_overlap = overlap;
+            separating_axis = index;
+        }
+    }
+
+    if least_overlap >= 0.0 {
+        let axis = axes[separating_axis];
+        let normal = if (*rhs - *lhs).scalar_product(&axis) < 0.0 { -axis } else { axis };
+        let points = if separating_axis < 2 {
+            let deepest = support_corner(&rhs_corners, &-normal);
+            [deepest + normal * least_overlap, deepest]
+        } else {
+            let deepest = support_corner(&lhs_corners, &normal);
+            [deepest, deepest - normal * least_overlap]
+        };
+
+        return ClosestPoints {
+            distance: -least_overlap,
+            points,
+            normal,
+        };
+    }
+
+    // Apart, so one of the corners is closest to the other square
+    let mut candidates = vec![];
+    for corner in &lhs_corners {
+        let nearest = nearest_point_on_square(rhs, rhs_edge, rhs_angle, corner);
+        candidates.push([*corner, nearest]);
+    }
+    for corner in &rhs_corners {
+        let nearest = nearest_point_on_square(lhs, lhs_edge, lhs_angle, corner);
+        candidates.push([nearest, *corner]);
+    }
+
+    let gap = |points: &[Vec2; 2]| (points[1] - points[0]).len_squared();
+    let points = candidates
+        .into_iter()
+        .min_by(|lhs, rhs| gap(lhs).total_cmp(&gap(rhs)))
+        .unwrap();
+
+    ClosestPoints {
+        distance: (points[1] - points[0]).len(),
+        points,
+        normal: direction_or_default(points[1] - points[0]),
+    }
+}
+
+fn square_vs_square(
+    lhs: &Vec2,
+    lhs_edge: f64,
+    lhs_angle: f64,
+    rhs: &Vec2,
+    rhs_edge: f64,
+    rhs_angle: f64,
+) -> ClosestPoints {
+    if lhs_angle != rhs_angle {
+        return rotated_square_vs_square(lhs, lhs_edge, lhs_angle, rhs, rhs_edge, rhs_angle);
+    }
+
+    if lhs_angle == 0.0 {
+        return aligned_square_vs_square(lhs, lhs_edge, rhs, rhs_edge);
+    }
+
+    // Turned alike, so the edges line up in the frame of either square
+    let local_rhs = (*rhs - *lhs).rotated(-lhs_angle);
+    let local = aligned_square_vs_square(&Vec2::new(), lhs_edge, &local_rhs, rhs_edge);
+    to_world(local, lhs, lhs_angle)
+}
+
+pub fn closest_points(lhs: &Body, rhs: &Body) -> ClosestPoints {
+    match (*lhs.get_shape(), *rhs.get_shape()) {
+        (Shape::Circle { radius: lhs_radius }, Shape::Circle { radius: rhs_radius }) => {
+            circle_vs_circle(&lhs.position, lhs_radius, &rhs.position, rhs_radius)
+        }
+        (Shape::Square { edge_length: lhs_edge }, Shape::Square { edge_length: rhs_edge }) => {
+            square_vs_square(&lhs.position, lhs_edge, lhs.angle, &rhs.position, rhs_edge, rhs.angle)
+        }
+        (Shape::Circle { radius }, Shape::Square { edge_length }) => {
+            circle_vs_square(&lhs.position, radius, &rhs.position, edge_length, rhs.angle)
+        }
+        (Shape::Square { edge_length }, Shape::Circle { radius }) => {
+            let mut result =
+                circle_vs_square(&rhs.position, radius, &lhs.position, edge_length, lhs.angle);
+            result.points.swap(0, 1);
+            result.normal = -result.normal;
+            result
+        }
+    }
+}
+
+pub fn distance(lhs: &Body, rhs: &Body) -> f64 {
+    closest_points(lhs, rhs).distance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitive::Mass;
+    use std::f64::consts::FRAC_PI_4;
+
+    // A square of edge 2 turned onto its corner, which reaches out to (sqrt(2), 0)
+    fn diamond_at(position: Vec2) -> Body {
+        let mut body = Body::new(Shape::Square { edge_length: 2.0 }, position, Mass::Infinity);
+        body.angle = FRAC_PI_4;
+        body
+    }
+
+    fn square_at(x: f64) -> Body {
+        Body::new(Shape::Square { edge_length: 2.0 }, Vec2::new_at(x, 0.0), Mass::Infinity)
+    }
+
+    fn circle_at(radius: f64, position: Vec2) -> Body {
+        Body::new(Shape::Circle { radius }, position, Mass::Infinity)
+    }
+
+    fn assert_close(expected: Vec2, actual: Vec2) {
+        assert!((expected - actual).len() < 1e-9, "{expected:?} != {actual:?}");
+    }
+
+    #[test]
+    fn test_separated_squares() {
+        let lhs = Body::new(Shape::Square { edge_length: 2.0 }, Vec2::new(), Mass::Infinity);
+        let rhs = Body::new(
+            Shape::Square { edge_length: 2.0 },
+            Vec2::new_at(5.0, 5.0),
+            Mass::Infinity,
+        );
+
+        let result = closest_points(&lhs, &rhs);
+        assert_eq!(Vec2::new_at(3.0, 3.0).len(), result.distance);
+        assert_eq!(Vec2::new_at(1.0, 1.0), result.points[0]);
+        assert_eq!(Vec2::new_at(4.0, 4.0), result.points[1]);
+    }
+
+    #[test]
+    fn test_penetrating_circle_and_square() {
+        let lhs = Body::new(Shape::Square { edge_length: 4.0 }, Vec2::new(), Mass::Infinity);
+        let rhs = Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(1.5, 0.0),
+            Mass::Infinity,
+        );
+
+        let result = closest_points(&lhs, &rhs);
+        assert_eq!(-1.5, result.distance);
+        assert_eq!(Vec2::new_at(1.0, 0.0), result.normal);
+        assert_eq!(Vec2::new_at(2.0, 0.0), result.points[0]);
+        assert_eq!(Vec2::new_at(0.5, 0.0), result.points[1]);
+    }
+
+    #[test]
+    fn test_circle_and_rotated_square() {
+        let lhs = circle_at(1.0, Vec2::new_at(3.0, 0.0));
+        let rhs = diamond_at(Vec2::new());
+        let corner = 2.0_f64.sqrt();
+
+        let result = closest_points(&lhs, &rhs);
+        assert!((result.distance - (2.0 - corner)).abs() < 1e-9);
+        assert_close(Vec2::new_at(2.0, 0.0), result.points[0]);
+        assert_close(Vec2::new_at(corner, 0.0), result.points[1]);
+        assert_close(Vec2::new_at(-1.0, 0.0), result.normal);
+
+        // Swapped, and inside the axis aligned box of the square but outside the square
+        let result = closest_points(&rhs, &circle_at(0.1, Vec2::new_at(0.9, 0.9)));
+        let face_distance = 1.8 / 2.0_f64.sqrt() - 1.0;
+        assert!((result.distance - (face_distance - 0.1)).abs() < 1e-9);
+        let face_normal = Vec2::new_polar(FRAC_PI_4, 1.0);
+        assert_close(face_normal, result.points[0]);
+        assert_close(face_normal, result.normal);
+
+        // Centered inside, pushed out through the nearest of the turned faces
+        let center = Vec2::new_at(1.0, 0.1);
+        let result = closest_points(&circle_at(0.5, center), &rhs);
+        let face_depth = 1.0 - 1.1 * FRAC_PI_4.cos();
+        assert!((result.distance + face_depth + 0.5).abs() < 1e-9);
+        let outward = Vec2::new_polar(FRAC_PI_4, 1.0);
+        assert_close(center + outward * face_depth, result.points[1]);
+        assert_close(-outward, result.normal);
+    }
+
+    #[test]
+    fn test_rotated_and_aligned_squares() {
+        let lhs = diamond_at(Vec2::new());
+        let corner = 2.0_f64.sqrt();
+
+        let result = closest_points(&lhs, &square_at(4.0));
+        assert!((result.distance - (3.0 - corner)).abs() < 1e-9);
+        assert_close(Vec2::new_at(corner, 0.0), result.points[0]);
+        assert_close(Vec2::new_at(3.0, 0.0), result.points[1]);
+        assert_close(Vec2::new_at(1.0, 0.0), result.normal);
+
+        // Only the corner reaches into the other square
+        let result = closest_points(&lhs, &square_at(2.2));
+        assert!((result.distance - (1.2 - corner)).abs() < 1e-9);
+        assert_close(Vec2::new_at(corner, 0.0), result.points[0]);
+        assert_close(Vec2::new_at(1.2, 0.0), result.points[1]);
+        assert_close(Vec2::new_at(1.0, 0.0), result.normal);
+    }
+
+    #[test]
+    fn test_squares_turned_alike() {
+        let lhs = diamond_at(Vec2::new());
+        let direction = Vec2::new_polar(FRAC_PI_4, 1.0);
+        let rhs = diamond_at(direction * 4.0);
+
+        // Face to face along the diagonal, where the axis aligned boxes would overlap
+        let result = closest_points(&lhs, &rhs);
+        assert!((result.distance - 2.0).abs() < 1e-9);
+        assert_close(direction, result.points[0]);
+        assert_close(direction * 3.0, result.points[1]);
+        assert_close(direction, result.normal);
+    }
+}
@@ -2,8 +2,16 @@ mod collider;
 mod collision_detection;
 
 pub mod collision_event;
+pub mod distance;
+pub mod point_query;
+pub mod ray_cast;
+pub mod shape_cast;
 pub mod solver;
 pub use collision_event::CollisionEvent;
+pub use distance::closest_points;
+pub use point_query::contains_point;
+pub use ray_cast::cast_ray;
+pub use shape_cast::cast_shape;
 pub use solver::detect_collision;
 pub use solver::resolve_collision;
-pub use solver::detect_and_resolve_collision;
\ No newline at end of file
+pub use solver::detect_and_resolve_collision;
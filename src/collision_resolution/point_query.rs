@@ -0,0 +1,14 @@
+use crate::primitive::{Body, Shape, Vec2};
+
+pub fn contains_point(body: &Body, point: &Vec2) -> bool {
+    let delta = *point - body.position;
+    match *body.get_shape() {
+        Shape::Circle { radius } => delta.len_squared() <= radius * radius,
+        Shape::Square { edge_length } => {
+            // Checked in the frame of the square, where its edges are axis aligned
+            let local = delta.rotated(-body.angle);
+            let half_edge_length = edge_length / 2.0;
+            local.x.abs() <= half_edge_length && local.y.abs() <= half_edge_length
+        }
+    }
+}
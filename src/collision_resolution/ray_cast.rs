@@ -0,0 +1,172 @@
+use crate::primitive::{Body, Shape, Vec2};
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RayIntersection {
+    pub toi: f64,
+    pub normal: Vec2,
+}
+
+pub fn ray_vs_circle(
+    center: &Vec2,
+    radius: f64,
+    origin: &Vec2,
+    dir: &Vec2,
+    max_toi: f64,
+) -> Option<RayIntersection> {
+    let a = dir.len_squared();
+    if a == 0.0 {
+        return None;
+    }
+
+    let offset = *origin - *center;
+    let c = offset.len_squared() - radius * radius;
+
+    if c <= 0.0 {
+        // The ray starts inside the circle
+        let mut normal = if offset.len_squared() > 0.0 { offset } else { -*dir };
+        normal.normalize();
+        return Some(RayIntersection { toi: 0.0, normal });
+    }
+
+    let b = offset.scalar_product(dir);
+    if b > 0.0 {
+        // Pointing away from the circle
+        return None;
+    }
+
+    let discriminant = b * b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let toi = (-b - discriminant.sqrt()) / a;
+    if toi > max_toi {
+        return None;
+    }
+
+    let mut normal = *origin + *dir * toi - *center;
+    normal.normalize();
+
+    Some(RayIntersection { toi, normal })
+}
+
+pub fn ray_vs_square(
+    center: &Vec2,
+    edge_length: f64,
+    origin: &Vec2,
+    dir: &Vec2,
+    max_toi: f64,
+) -> Option<RayIntersection> {
+    let half_extents = Vec2::new_at(edge_length / 2.0, edge_length / 2.0);
+    ray_vs_box(center, &half_extents, origin, dir, max_toi)
+}
+
+pub fn ray_vs_box(
+    center: &Vec2,
+    half_extents: &Vec2,
+    origin: &Vec2,
+    dir: &Vec2,
+    max_toi: f64,
+) -> Option<RayIntersection> {
+    if dir.len_squared() == 0.0 {
+        return None;
+    }
+
+    let local_origin = *origin - *center;
+
+    let mut t_min: f64 = 0.0;
+    let mut t_max = max_toi;
+    let mut normal = Vec2::default();
+
+    let slabs = [
+        (local_origin.x, dir.x, half_extents.x, Vec2::new_at(1.0, 0.0)),
+        (local_origin.y, dir.y, half_extents.y, Vec2::new_at(0.0, 1.0)),
+    ];
+
+    for (origin, dir, half_extent, axis) in slabs {
+        if dir == 0.0 {
+            if origin.abs() > half_extent {
+                return None;
+            }
+            continue;
+        }
+
+        let inverse_dir = 1.0 / dir;
+        let t_near = (-half_extent - origin) * inverse_dir;
+        let t_far = (half_extent - origin) * inverse_dir;
+        let (t_near, t_far, face_normal) = if t_near <= t_far {
+            (t_near, t_far, -axis)
+        } else {
+            (t_far, t_near, axis)
+        };
+
+        if t_near > t_min {
+            t_min = t_near;
+            normal = face_normal;
+        }
+        t_max = t_max.min(t_far);
+
+        if t_min > t_max {
+            return None;
+        }
+    }
+
+    if normal == Vec2::default() {
+        // The ray starts inside the box
+        normal = -*dir;
+        normal.normalize();
+    }
+
+    Some(RayIntersection { toi: t_min, normal })
+}
+
+pub fn cast_ray(body: &Body, origin: &Vec2, dir: &Vec2, max_toi: f64) -> Option<RayIntersection> {
+    match *body.get_shape() {
+        Shape::Circle { radius } => ray_vs_circle(&body.position, radius, origin, dir, max_toi),
+        Shape::Square { edge_length } if body.angle == 0.0 => {
+            ray_vs_square(&body.position, edge_length, origin, dir, max_toi)
+        }
+        Shape::Square { edge_length } => {
+            // Cast in the frame of the square, the time of impact stays the same
+            let local_origin = (*origin - body.position).rotated(-body.angle);
+            let local_dir = dir.rotated(-body.angle);
+            let mut intersection =
+                ray_vs_square(&Vec2::new(), edge_length, &local_origin, &local_dir, max_toi)?;
+            intersection.normal = intersection.normal.rotated(body.angle);
+            Some(intersection)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ray_vs_circle() {
+        let center = Vec2::new_at(10.0, 0.0);
+        let origin = Vec2::new();
+        let dir = Vec2::new_at(1.0, 0.0);
+
+        let hit = ray_vs_circle(&center, 2.0, &origin, &dir, 100.0).unwrap();
+        assert_eq!(8.0, hit.toi);
+        assert_eq!(Vec2::new_at(-1.0, 0.0), hit.normal);
+
+        assert!(ray_vs_circle(&center, 2.0, &origin, &-dir, 100.0).is_none());
+        assert!(ray_vs_circle(&center, 2.0, &origin, &dir, 5.0).is_none());
+    }
+
+    #[test]
+    fn test_ray_vs_square() {
+        let center = Vec2::new_at(0.0, 10.0);
+        let origin = Vec2::new_at(1.0, 0.0);
+        let dir = Vec2::new_at(0.0, 2.0);
+
+        let hit = ray_vs_square(&center, 4.0, &origin, &dir, 100.0).unwrap();
+        assert_eq!(4.0, hit.toi);
+        assert_eq!(Vec2::new_at(0.0, -1.0), hit.normal);
+
+        let miss_origin = Vec2::new_at(3.0, 0.0);
+        assert!(ray_vs_square(&center, 4.0, &miss_origin, &dir, 100.0).is_none());
+    }
+}
@@ -0,0 +1,163 @@
+use crate::primitive::{Body, Mass, Shape, Vec2};
+
+use super::distance::closest_points;
+
+// Closer than this the shapes count as touching
+const CONTACT_TOLERANCE: f64 = 1e-9;
+const MAX_ITERATIONS: usize = 64;
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ShapeCastIntersection {
+    // Fraction of the displacement at which the shapes start touching
+    pub toi: f64,
+    pub witness_on_shape: Vec2,
+    pub witness_on_body: Vec2,
+    // Points from the hit body towards the cast shape
+    pub normal: Vec2,
+}
+
+// Conservative advancement on the closest points query, so the cast sees the same geometry,
+// rotation included, as the distance queries. The shapes only translate, which keeps their
+// distance a convex function of the fraction, so stepping to where the closing speed along
+// the normal would make them touch never overshoots. A hit that merely grazes the body may
+// not converge within the iteration budget and is reported as a miss
+pub fn cast_shape(
+    shape: &Shape,
+    from: &Vec2,
+    displacement: &Vec2,
+    body: &Body,
+) -> Option<ShapeCastIntersection> {
+    let mut probe = Body::new(*shape, *from, Mass::Infinity);
+    let mut toi = 0.0;
+
+    for _ in 0..MAX_ITERATIONS {
+        probe.position = *from + *displacement * toi;
+        let closest = closest_points(&probe, body);
+
+        if closest.distance <= CONTACT_TOLERANCE {
+            return Some(ShapeCastIntersection {
+                toi,
+                witness_on_shape: closest.points[0],
+                witness_on_body: closest.points[1],
+                normal: -closest.normal,
+            });
+        }
+
+        let closing_speed = displacement.scalar_product(&closest.normal);
+        if closing_speed <= 0.0 {
+            // Moving apart, or sliding past without getting closer
+            return None;
+        }
+
+        toi += closest.distance / closing_speed;
+        if toi > 1.0 {
+            return None;
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitive::Mass;
+    use std::f64::consts::FRAC_PI_4;
+
+    #[test]
+    fn test_circle_vs_square() {
+        let body = Body::new(
+            Shape::Square { edge_length: 2.0 },
+            Vec2::new_at(10.0, 0.0),
+            Mass::Infinity,
+        );
+        let shape = Shape::Circle { radius: 1.0 };
+
+        let hit = cast_shape(&shape, &Vec2::new(), &Vec2::new_at(20.0, 0.0), &body).unwrap();
+        assert_eq!(0.4, hit.toi);
+        assert_eq!(Vec2::new_at(-1.0, 0.0), hit.normal);
+        assert_eq!(Vec2::new_at(9.0, 0.0), hit.witness_on_body);
+        assert_eq!(Vec2::new_at(9.0, 0.0), hit.witness_on_shape);
+    }
+
+    #[test]
+    fn test_square_vs_circle() {
+        let body = Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(0.0, 10.0),
+            Mass::Infinity,
+        );
+        let shape = Shape::Square { edge_length: 2.0 };
+
+        let hit = cast_shape(&shape, &Vec2::new(), &Vec2::new_at(0.0, 16.0), &body).unwrap();
+        assert_eq!(0.5, hit.toi);
+        assert_eq!(Vec2::new_at(0.0, -1.0), hit.normal);
+        assert_eq!(Vec2::new_at(0.0, 9.0), hit.witness_on_body);
+    }
+
+    #[test]
+    fn test_circle_vs_square_corner() {
+        let body = Body::new(
+            Shape::Square { edge_length: 2.0 },
+            Vec2::new_at(5.0, 1.5),
+            Mass::Infinity,
+        );
+        let shape = Shape::Circle { radius: 1.0 };
+
+        // The circle runs into the lower left corner at (4, 0.5) off center, so the
+        // advancement takes several steps
+        let hit = cast_shape(&shape, &Vec2::new(), &Vec2::new_at(10.0, 0.0), &body).unwrap();
+        let expected_x = 4.0 - 0.75_f64.sqrt();
+        assert!((hit.toi - expected_x / 10.0).abs() < 1e-9);
+        assert!((hit.witness_on_body - Vec2::new_at(4.0, 0.5)).len() < 1e-9);
+        assert!((hit.normal - Vec2::new_at(expected_x - 4.0, -0.5)).len() < 1e-9);
+    }
+
+    #[test]
+    fn test_misses() {
+        let body = Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(10.0, 0.0),
+            Mass::Infinity,
+        );
+        let shape = Shape::Square { edge_length: 2.0 };
+
+        // Moving away, passing by and stopping short
+        assert!(cast_shape(&shape, &Vec2::new(), &Vec2::new_at(-20.0, 0.0), &body).is_none());
+        assert!(cast_shape(&shape, &Vec2::new(), &Vec2::new_at(20.0, 5.0), &body).is_none());
+        assert!(cast_shape(&shape, &Vec2::new(), &Vec2::new_at(7.0, 0.0), &body).is_none());
+    }
+
+    #[test]
+    fn test_initially_overlapping() {
+        let body = Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(1.5, 0.0),
+            Mass::Infinity,
+        );
+        let shape = Shape::Circle { radius: 1.0 };
+
+        let hit = cast_shape(&shape, &Vec2::new(), &Vec2::new_at(-5.0, 0.0), &body).unwrap();
+        assert_eq!(0.0, hit.toi);
+        assert_eq!(Vec2::new_at(-1.0, 0.0), hit.normal);
+    }
+
+    #[test]
+    fn test_circle_vs_rotated_square() {
+        let mut body = Body::new(Shape::Square { edge_length: 2.0 }, Vec2::new(), Mass::Infinity);
+        let shape = Shape::Circle { radius: 0.1 };
+        let from = Vec2::new_at(-5.0, 1.3);
+        let displacement = Vec2::new_at(10.0, 0.0);
+
+        // Passes above the square, but not above its upper corner once it is turned
+        assert!(cast_shape(&shape, &from, &displacement, &body).is_none());
+        body.angle = FRAC_PI_4;
+
+        let hit = cast_shape(&shape, &from, &displacement, &body).unwrap();
+        let contact_x = 1.3 - 1.1 * 2.0_f64.sqrt();
+        assert!((hit.toi - (contact_x + 5.0) / 10.0).abs() < 1e-9);
+        let face_normal = Vec2::new_polar(3.0 * FRAC_PI_4, 1.0);
+        assert!((hit.normal - face_normal).len() < 1e-9);
+        assert!((hit.witness_on_body - hit.witness_on_shape).len() < 1e-9);
+    }
+}
@@ -55,11 +55,11 @@ pub fn resolve_collision(lhs: &mut Body, rhs: &mut Body, collision: &CollisionEv
 
     let lhs_move_per_inverse_mass =
         collision.normal * (collision.penetration_depth / total_inverse_mass);
-    let _rhs_move_per_inverse_mass =
+    let rhs_move_per_inverse_mass =
         collision.normal * (collision.penetration_depth / -total_inverse_mass);
 
     lhs.position += lhs_move_per_inverse_mass.scaled(lhs_inverse_mass);
-    //rhs.position += rhs_move_per_inverse_mass.scaled(rhs_inverse_mass);
+    rhs.position += rhs_move_per_inverse_mass.scaled(rhs_inverse_mass);
 
     // Do post-processing for forced separation where the velocity based
     // position correction was unsuccessful, e.g. due to bodies which
@@ -85,3 +85,78 @@ pub fn detect_and_resolve_collision(lhs: &mut Body, rhs: &mut Body) -> Option<Co
     resolve_collision(lhs, rhs, &collision);
     Some(collision)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsp::aabb::Aabb;
+    use crate::collision_resolution::{cast_ray, contains_point};
+    use crate::primitive::{Mass, Shape, Vec2};
+    use std::f64::consts::FRAC_PI_4;
+
+    fn square_at(x: f64) -> Body {
+        Body::new(Shape::Square { edge_length: 2.0 }, Vec2::new_at(x, 0.0), Mass::Infinity)
+    }
+
+    fn circle_at(radius: f64, position: Vec2) -> Body {
+        Body::new(Shape::Circle { radius }, position, Mass::Infinity)
+    }
+
+    fn diamond() -> Body {
+        let mut body = square_at(0.0);
+        body.angle = FRAC_PI_4;
+        body
+    }
+
+    #[test]
+    fn test_rotated_square_queries() {
+        let mut body = diamond();
+        let half_diagonal = 2.0_f64.sqrt();
+
+        assert!(contains_point(&body, &Vec2::new_at(1.3, 0.0)));
+        assert!(!contains_point(&body, &Vec2::new_at(0.9, 0.9)));
+
+        let aabb = Aabb::from(&body);
+        assert!((aabb.bottom_right.x - half_diagonal).abs() < 1e-9);
+        assert!((aabb.top_left.y - half_diagonal).abs() < 1e-9);
+
+        let hit = cast_ray(&body, &Vec2::new_at(-5.0, 0.0), &Vec2::new_at(1.0, 0.0), 10.0).unwrap();
+        assert!((hit.toi - (5.0 - half_diagonal)).abs() < 1e-9);
+        assert!(hit.normal.x < 0.0 && hit.normal.y.abs() > 0.5);
+
+        body.angle = 0.0;
+        assert!(!contains_point(&body, &Vec2::new_at(1.3, 0.0)));
+        assert!(contains_point(&body, &Vec2::new_at(0.9, 0.9)));
+    }
+
+    #[test]
+    fn test_rotated_square_vs_circle() {
+        let mut square = diamond();
+
+        // Past the edge of the square, but only inside its axis aligned box
+        let mut inside_box = circle_at(0.2, Vec2::new_at(0.9, 0.9));
+        assert!(detect_collision(&mut inside_box, &mut square).is_none());
+
+        // Touched by the corner only
+        let mut at_corner = circle_at(0.3, Vec2::new_at(1.6, 0.0));
+        let collision = detect_collision(&mut at_corner, &mut square).unwrap();
+        assert!((collision.normal.x - 1.0).abs() < 1e-9);
+        assert!((collision.penetration_depth - (0.3 - (1.6 - 2.0_f64.sqrt()))).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_rotated_square_vs_square() {
+        let mut lhs = diamond();
+        let mut rhs = square_at(2.2);
+
+        let collision = detect_collision(&mut lhs, &mut rhs).unwrap();
+        let depth = 2.0_f64.sqrt() - 1.2;
+        assert!((collision.normal.x + 1.0).abs() < 1e-9 && collision.normal.y.abs() < 1e-9);
+        assert!((collision.penetration_depth - depth).abs() < 1e-9);
+        assert!((collision.contact.x - (2.0_f64.sqrt() - depth / 2.0)).abs() < 1e-9);
+
+        // The same squares without the rotation are apart
+        lhs.angle = 0.0;
+        assert!(detect_collision(&mut lhs, &mut rhs).is_none());
+    }
+}
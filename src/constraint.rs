@@ -1,14 +1,406 @@
+use std::{any::Any, f64::consts::PI};
+
 use crate::{
-    primitive::{Body, Mass},
+    collision_resolution::CollisionEvent,
+    math,
+    primitive::{Body, Mass, Shape, Vec2},
     world::Entity,
 };
 
-pub trait Constraint {
+// Fraction of the position error rigid joints correct per step
+const BAUMGARTE: f64 = 0.2;
+
+// Gets from a constraint back to its concrete type, e.g. to drive a motor
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// Serializable stand-in for boxed trait objects, tagged with the name of the concrete
+// type. Types missing from the list can't be saved
+#[cfg(feature = "serde")]
+macro_rules! tagged_registry {
+    ($record:ident: dyn $trait:ident { $($name:ident),* $(,)? }) => {
+        #[derive(serde::Serialize, serde::Deserialize)]
+        #[serde(tag = "type")]
+        pub enum $record {
+            $($name($name),)*
+        }
+
+        impl $record {
+            pub fn from_dyn(value: &dyn $trait) -> Option<Self> {
+                let any = value.as_any();
+                $(
+                    if let Some(value) = any.downcast_ref::<$name>() {
+                        return Some(Self::$name(value.clone()));
+                    }
+                )*
+                None
+            }
+
+            pub fn into_boxed(self) -> Box<dyn $trait> {
+                match self {
+                    $(Self::$name(value) => Box::new(value),)*
+                }
+            }
+        }
+    };
+}
+#[cfg(feature = "serde")]
+pub(crate) use tagged_registry;
+
+// Copies a boxed constraint, e.g. for snapshots of the world
+pub trait ConstraintClone {
+    fn clone_box(&self) -> Box<dyn Constraint>;
+}
+
+impl<T: Constraint + Clone + 'static> ConstraintClone for T {
+    fn clone_box(&self) -> Box<dyn Constraint> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Constraint> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+pub trait Constraint: AsAny + ConstraintClone {
     fn get_first_entity(&self) -> Entity;
     fn get_second_entity(&self) -> Entity;
-    fn process(&self, lhs: &mut Body, rhs: &Body, delta: f64);
+
+    // Force based constraints, applied before the bodies get integrated
+    fn process(&self, _lhs: &mut Body, _rhs: &mut Body, _delta: f64) {}
+
+    // Joints solved by the iterative solver, `prepare` runs once per step and
+    // `solve_velocity` once per solver iteration
+    fn prepare(&mut self, _lhs: &Body, _rhs: &Body, _delta: f64) {}
+    fn solve_velocity(&mut self, _lhs: &mut Body, _rhs: &mut Body, _delta: f64) {}
+
+    // Position based constraints replace `process` and `solve_velocity` in the XPBD
+    // solver, there `prepare` and `solve_position` run once per substep
+    fn is_position_based(&self) -> bool {
+        false
+    }
+    fn solve_position(&mut self, _lhs: &mut Body, _rhs: &mut Body, _delta: f64) {}
+
+    // Magnitude of the impulse applied during the last step
+    fn applied_impulse(&self) -> f64 {
+        0.0
+    }
+}
+
+// Joints acting on a single body, e.g. towards a point in the world. Solved along with
+// the velocity joints of `Constraint`
+pub trait UnaryConstraintClone {
+    fn clone_box(&self) -> Box<dyn UnaryConstraint>;
+}
+
+impl<T: UnaryConstraint + Clone + 'static> UnaryConstraintClone for T {
+    fn clone_box(&self) -> Box<dyn UnaryConstraint> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn UnaryConstraint> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+pub trait UnaryConstraint: AsAny + UnaryConstraintClone {
+    fn get_entity(&self) -> Entity;
+
+    fn prepare(&mut self, _body: &Body, _delta: f64) {}
+    fn solve_velocity(&mut self, _body: &mut Body, _delta: f64) {}
+
+    fn applied_impulse(&self) -> f64 {
+        0.0
+    }
+}
+
+// Lets a joint give way like a spring, a frequency of zero keeps it rigid
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Softness {
+    pub frequency: f64,
+    pub damping_ratio: f64,
+}
+
+struct SoftCoefficients {
+    bias_rate: f64,
+    mass_scale: f64,
+    impulse_scale: f64,
+}
+
+impl Softness {
+    pub const fn rigid() -> Self {
+        Self {
+            frequency: 0.0,
+            damping_ratio: 0.0,
+        }
+    }
+
+    pub const fn new(frequency: f64, damping_ratio: f64) -> Self {
+        Self {
+            frequency,
+            damping_ratio,
+        }
+    }
+
+    // XPBD compliance of a spring with this frequency between bodies of `inverse_mass`
+    fn compliance(&self, inverse_mass: f64) -> f64 {
+        if self.frequency <= 0.0 {
+            return 0.0;
+        }
+
+        let angular_frequency = 2.0 * PI * self.frequency;
+        inverse_mass / (angular_frequency * angular_frequency)
+    }
+
+    fn coefficients(&self, delta: f64) -> SoftCoefficients {
+        if self.frequency <= 0.0 {
+            return SoftCoefficients {
+                bias_rate: BAUMGARTE / delta,
+                mass_scale: 1.0,
+                impulse_scale: 0.0,
+            };
+        }
+
+        let omega = 2.0 * PI * self.frequency;
+        let a1 = 2.0 * self.damping_ratio + delta * omega;
+        let a2 = delta * omega * a1;
+        let a3 = 1.0 / (1.0 + a2);
+        SoftCoefficients {
+            bias_rate: omega / a1,
+            mass_scale: a2 * a3,
+            impulse_scale: a3,
+        }
+    }
+}
+
+impl SoftCoefficients {
+    // For rows which compute their bias themselves
+    const HARD: Self = Self {
+        bias_rate: 0.0,
+        mass_scale: 1.0,
+        impulse_scale: 0.0,
+    };
+}
+
+// A single scalar constraint between two bodies, the levers couple it to their rotations
+#[derive(Clone, Copy)]
+struct Row {
+    direction: Vec2,
+    lever1: f64,
+    lever2: f64,
+}
+
+impl Row {
+    // Pulls along `direction` at the anchors `r1` and `r2` away from the body centers
+    fn linear(direction: Vec2, r1: &Vec2, r2: &Vec2) -> Self {
+        Self {
+            direction,
+            lever1: r1.cross(&direction),
+            lever2: r2.cross(&direction),
+        }
+    }
+
+    // Only acts on the relative rotation
+    const fn angular() -> Self {
+        Self {
+            direction: Vec2::new(),
+            lever1: 1.0,
+            lever2: 1.0,
+        }
+    }
+
+    const fn negated(&self) -> Self {
+        Self {
+            direction: Vec2::new_at(-self.direction.x, -self.direction.y),
+            lever1: -self.lever1,
+            lever2: -self.lever2,
+        }
+    }
+
+    fn inverse_effective_mass(&self, lhs: &Body, rhs: &Body) -> f64 {
+        let inverse_mass = lhs.get_mass().get_inverse() + rhs.get_mass().get_inverse();
+        self.direction.len_squared() * inverse_mass
+            + self.lever1 * self.lever1 * lhs.get_inverse_inertia()
+            + self.lever2 * self.lever2 * rhs.get_inverse_inertia()
+    }
+
+    fn velocity(&self, lhs: &Body, rhs: &Body) -> f64 {
+        (rhs.velocity - lhs.velocity).scalar_product(&self.direction)
+            + self.lever2 * rhs.angular_velocity
+            - self.lever1 * lhs.angular_velocity
+    }
+
+    // Impulse which drives the velocity plus `bias` to zero
+    fn impulse(
+        &self,
+        lhs: &Body,
+        rhs: &Body,
+        bias: f64,
+        soft: &SoftCoefficients,
+        accumulated: f64,
+    ) -> f64 {
+        let inverse_effective_mass = self.inverse_effective_mass(lhs, rhs);
+        if inverse_effective_mass <= 0.0 {
+            return 0.0;
+        }
+
+        -soft.mass_scale * (self.velocity(lhs, rhs) + bias) / inverse_effective_mass
+            - soft.impulse_scale * accumulated
+    }
+
+    fn apply(&self, lhs: &mut Body, rhs: &mut Body, impulse: f64) {
+        lhs.velocity -= self.direction * (impulse * lhs.get_mass().get_inverse());
+        lhs.angular_velocity -= self.lever1 * impulse * lhs.get_inverse_inertia();
+        rhs.velocity += self.direction * (impulse * rhs.get_mass().get_inverse());
+        rhs.angular_velocity += self.lever2 * impulse * rhs.get_inverse_inertia();
+    }
+
+    // Keeps the constrained value from going below its limit, `distance` being how far
+    // above the limit it currently is
+    fn solve_limit(
+        &self,
+        lhs: &mut Body,
+        rhs: &mut Body,
+        distance: f64,
+        delta: f64,
+        accumulated: &mut f64,
+    ) {
+        let bias = if distance > 0.0 {
+            // Not touching the limit yet, only stop what would cross it this step
+            distance / delta
+        } else {
+            distance * BAUMGARTE / delta
+        };
+
+        let impulse = self.impulse(lhs, rhs, bias, &SoftCoefficients::HARD, 0.0);
+        let previous_impulse = *accumulated;
+        *accumulated = (previous_impulse + impulse).max(0.0);
+        self.apply(lhs, rhs, *accumulated - previous_impulse);
+    }
+
+    fn solve_motor(
+        &self,
+        lhs: &mut Body,
+        rhs: &mut Body,
+        speed: f64,
+        max_impulse: f64,
+        accumulated: &mut f64,
+    ) {
+        let impulse = self.impulse(lhs, rhs, -speed, &SoftCoefficients::HARD, 0.0);
+        let previous_impulse = *accumulated;
+        *accumulated = (previous_impulse + impulse).clamp(-max_impulse, max_impulse);
+        self.apply(lhs, rhs, *accumulated - previous_impulse);
+    }
+}
+
+// Anchors relative to the body centers in world space and the vector between them
+fn anchors(
+    lhs: &Body,
+    local_anchor1: &Vec2,
+    rhs: &Body,
+    local_anchor2: &Vec2,
+) -> (Vec2, Vec2, Vec2) {
+    let r1 = local_anchor1.rotated(lhs.angle);
+    let r2 = local_anchor2.rotated(rhs.angle);
+    (r1, r2, (rhs.position + r2) - (lhs.position + r1))
+}
+
+// XPBD projection of a distance constraint with the current `error` along `normal`,
+// `lambda` accumulates the lagrange multiplier over the substep
+fn project_distance(
+    lhs: &mut Body,
+    rhs: &mut Body,
+    normal: &Vec2,
+    error: f64,
+    compliance: f64,
+    lambda: &mut f64,
+    delta: f64,
+) {
+    let (lhs_inverse_mass, rhs_inverse_mass) =
+        (lhs.get_mass().get_inverse(), rhs.get_mass().get_inverse());
+    let scaled_compliance = compliance / (delta * delta);
+    let denominator = lhs_inverse_mass + rhs_inverse_mass + scaled_compliance;
+    if denominator <= 0.0 {
+        return;
+    }
+
+    let delta_lambda = (-error - scaled_compliance * *lambda) / denominator;
+    *lambda += delta_lambda;
+    lhs.position -= *normal * (delta_lambda * lhs_inverse_mass);
+    rhs.position += *normal * (delta_lambda * rhs_inverse_mass);
+}
+
+// Pulls both anchors onto each other, one row per axis
+fn solve_point(
+    lhs: &mut Body,
+    rhs: &mut Body,
+    (r1, r2, error): (Vec2, Vec2, Vec2),
+    soft: &SoftCoefficients,
+    accumulated: &mut Vec2,
+) {
+    let rows = [
+        (Vec2::new_at(1.0, 0.0), error.x, &mut accumulated.x),
+        (Vec2::new_at(0.0, 1.0), error.y, &mut accumulated.y),
+    ];
+
+    for (axis, error, accumulated) in rows {
+        let row = Row::linear(axis, &r1, &r2);
+        let impulse = row.impulse(lhs, rhs, error * soft.bias_rate, soft, *accumulated);
+        *accumulated += impulse;
+        row.apply(lhs, rhs, impulse);
+    }
+}
+
+// Exact solution of `x'' = -stiffness * x - damping * x'` after `time`, as the matrix which
+// maps `(x0, v0)` onto `(x, v)`. Both constants are per unit mass
+fn damped_oscillation(stiffness: f64, damping: f64, time: f64) -> [[f64; 2]; 2] {
+    let half_damping = 0.5 * damping;
+    let decay = math::exp(-half_damping * time);
+    let discriminant = damping * damping - 4.0 * stiffness;
+
+    if discriminant.abs() <= 1.0e-9 * (damping * damping + 4.0 * stiffness.abs()) {
+        // Critically damped, also covers a spring without stiffness and damping
+        [
+            [decay * (1.0 + half_damping * time), decay * time],
+            [-decay * (half_damping * half_damping) * time, decay * (1.0 - half_damping * time)],
+        ]
+    } else if discriminant < 0.0 {
+        let gamma = 0.5 * (-discriminant).sqrt();
+        let (sin, cos) = math::sin_cos(gamma * time);
+        [
+            [decay * (cos + half_damping / gamma * sin), decay * sin / gamma],
+            [-decay * stiffness / gamma * sin, decay * (cos - half_damping / gamma * sin)],
+        ]
+    } else {
+        // Overdamped, the sum of two decaying exponentials
+        let root = 0.5 * discriminant.sqrt();
+        let (slow, fast) = (-half_damping + root, -half_damping - root);
+        let (slow_decay, fast_decay) = (math::exp(slow * time), math::exp(fast * time));
+        let b = (slow_decay - fast_decay) / (slow - fast);
+        let d = (slow * slow_decay - fast * fast_decay) / (slow - fast);
+        [[fast_decay - fast * b, b], [fast * fast_decay - fast * d, d]]
+    }
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FakeSpring {
     pub entity1: Entity,
     pub entity2: Entity,
@@ -26,31 +418,24 @@ impl FakeSpring {
         }
     }
 
-    pub fn process(&self, lhs: &mut Body, rhs: &Body, delta: f64) {
-        if lhs.get_mass().is_infinite() {
+    pub fn process(&self, lhs: &mut Body, rhs: &mut Body, delta: f64) {
+        let inverse_mass = lhs.get_mass().get_inverse() + rhs.get_mass().get_inverse();
+        if inverse_mass <= 0.0 {
             return;
         }
 
-        let mass_value = if let Mass::Value(val) = *lhs.get_mass() {
-            val
-        } else {
-            return;
-        };
-
+        // The spring acts on the relative motion, which responds like the reduced mass
+        let reduced_mass = 1.0 / inverse_mass;
         let position = lhs.position - rhs.position;
+        let velocity = lhs.velocity - rhs.velocity;
 
-        let gamma = 0.5 * (4.0 * self.spring_constant - self.damping.powi(2)).sqrt();
-        if gamma == 0.0 {
-            return;
-        }
-
-        let c = position * (self.damping / (2.0 * gamma)) + lhs.velocity * (1.0 / gamma);
-
-        let mut target = position * (gamma * delta).cos() + c * (gamma * delta).sin();
-        target *= (-0.5 * delta * self.damping).exp();
+        let [[a, b], _] = damped_oscillation(self.spring_constant, self.damping, delta);
+        let target = position * a + velocity * b;
 
-        let accel = (target - position) * (1.0 / (delta.powi(2))) - lhs.velocity * (1.0 / delta);
-        lhs.force += accel * mass_value;
+        let accel = (target - position) * (1.0 / (delta * delta)) - velocity * (1.0 / delta);
+        let force = accel * reduced_mass;
+        lhs.force += force;
+        rhs.force -= force;
     }
 }
 
@@ -63,16 +448,115 @@ impl Constraint for FakeSpring {
         self.entity2
     }
 
-    fn process(&self, lhs: &mut Body, rhs: &Body, delta: f64) {
+    fn process(&self, lhs: &mut Body, rhs: &mut Body, delta: f64) {
         self.process(lhs, rhs, delta);
     }
 }
 
+// Spring with a rest length, the relative motion along it follows the exact solution of the
+// damped oscillator over each step, so stiff and overdamped springs stay stable
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DampedSpring {
+    pub entity1: Entity,
+    pub entity2: Entity,
+    pub rest_length: f64,
+    pub stiffness: f64,
+    pub damping: f64,
+    normal: Vec2,
+    target_velocity: f64,
+    // The bodies keep the average velocity over the step, this is what they lack of
+    // the exact one at its end
+    velocity_lag: f64,
+    impulse: f64,
+}
+
+impl DampedSpring {
+    pub fn new(
+        entity1: Entity,
+        entity2: Entity,
+        rest_length: f64,
+        stiffness: f64,
+        damping: f64,
+    ) -> Self {
+        Self {
+            entity1,
+            entity2,
+            rest_length,
+            stiffness,
+            damping,
+            normal: Vec2::new(),
+            target_velocity: 0.0,
+            velocity_lag: 0.0,
+            impulse: 0.0,
+        }
+    }
+
+    // Damping at which the spring stops oscillating between bodies of `reduced_mass`
+    pub fn critical_damping(stiffness: f64, reduced_mass: f64) -> f64 {
+        2.0 * (stiffness * reduced_mass).sqrt()
+    }
+}
+
+impl Constraint for DampedSpring {
+    fn get_first_entity(&self) -> Entity {
+        self.entity1
+    }
+
+    fn get_second_entity(&self) -> Entity {
+        self.entity2
+    }
+
+    fn prepare(&mut self, lhs: &Body, rhs: &Body, delta: f64) {
+        self.impulse = 0.0;
+        self.normal = Vec2::new();
+        self.target_velocity = 0.0;
+        let velocity_lag = std::mem::take(&mut self.velocity_lag);
+
+        let inverse_mass = lhs.get_mass().get_inverse() + rhs.get_mass().get_inverse();
+        let offset = rhs.position - lhs.position;
+        let length = offset.len();
+        if inverse_mass <= 0.0 || length == 0.0 || delta <= 0.0 {
+            return;
+        }
+
+        self.normal = offset * (1.0 / length);
+        let extension = length - self.rest_length;
+        let velocity = (rhs.velocity - lhs.velocity).scalar_product(&self.normal) + velocity_lag;
+
+        // The relative motion responds like the reduced mass
+        let [[a, b], [c, d]] = damped_oscillation(
+            self.stiffness * inverse_mass,
+            self.damping * inverse_mass,
+            delta,
+        );
+        let target = a * extension + b * velocity;
+        // The positions get integrated with the solved velocities and land on the target
+        self.target_velocity = (target - extension) / delta;
+        self.velocity_lag = c * extension + d * velocity - self.target_velocity;
+    }
+
+    fn solve_velocity(&mut self, lhs: &mut Body, rhs: &mut Body, _delta: f64) {
+        let row = Row::linear(self.normal, &Vec2::new(), &Vec2::new());
+        let bias = -self.target_velocity;
+        let impulse = row.impulse(lhs, rhs, bias, &SoftCoefficients::HARD, 0.0);
+        self.impulse += impulse;
+        row.apply(lhs, rhs, impulse);
+    }
+
+    fn applied_impulse(&self) -> f64 {
+        self.impulse.abs()
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BungeeSpring {
     pub entity1: Entity,
     pub entity2: Entity,
     pub distance: f64,
     pub spring_constant: f64,
+    lambda: f64,
 }
 
 impl BungeeSpring {
@@ -82,10 +566,11 @@ impl BungeeSpring {
             entity2,
             distance,
             spring_constant: 100.0,
+            lambda: 0.0,
         }
     }
 
-    pub fn process(&self, lhs: &mut Body, rhs: &Body, _delta: f64) {
+    pub fn process(&self, lhs: &mut Body, rhs: &mut Body, _delta: f64) {
         let mut force = lhs.position - rhs.position;
         let magnitude = force.len();
 
@@ -97,6 +582,7 @@ impl BungeeSpring {
         force.normalize();
         force *= -magnitude;
         lhs.force -= force;
+        rhs.force += force;
     }
 }
 
@@ -109,7 +595,1055 @@ impl Constraint for BungeeSpring {
         self.entity2
     }
 
-    fn process(&self, lhs: &mut Body, rhs: &Body, delta: f64) {
+    fn process(&self, lhs: &mut Body, rhs: &mut Body, delta: f64) {
         self.process(lhs, rhs, delta);
     }
+
+    fn is_position_based(&self) -> bool {
+        true
+    }
+
+    fn prepare(&mut self, _lhs: &Body, _rhs: &Body, _delta: f64) {
+        self.lambda = 0.0;
+    }
+
+    fn solve_position(&mut self, lhs: &mut Body, rhs: &mut Body, delta: f64) {
+        let anchor_delta = rhs.position - lhs.position;
+        let magnitude = anchor_delta.len();
+        if magnitude <= self.distance {
+            return;
+        }
+
+        // The same spring as a compliance, which stays stable at any stiffness
+        let normal = anchor_delta * (1.0 / magnitude);
+        let compliance = 1.0 / self.spring_constant;
+        let error = magnitude - self.distance;
+        project_distance(lhs, rhs, &normal, error, compliance, &mut self.lambda, delta);
+    }
+
+    fn applied_impulse(&self) -> f64 {
+        self.lambda.abs()
+    }
+}
+
+// Keeps the anchors at exactly `length` from each other
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DistanceJoint {
+    pub entity1: Entity,
+    pub entity2: Entity,
+    pub local_anchor1: Vec2,
+    pub local_anchor2: Vec2,
+    pub length: f64,
+    pub softness: Softness,
+    impulse: f64,
+}
+
+impl DistanceJoint {
+    pub fn new(entity1: Entity, entity2: Entity, length: f64) -> Self {
+        Self {
+            entity1,
+            entity2,
+            local_anchor1: Vec2::new(),
+            local_anchor2: Vec2::new(),
+            length,
+            softness: Softness::rigid(),
+            impulse: 0.0,
+        }
+    }
+}
+
+impl Constraint for DistanceJoint {
+    fn get_first_entity(&self) -> Entity {
+        self.entity1
+    }
+
+    fn get_second_entity(&self) -> Entity {
+        self.entity2
+    }
+
+    fn prepare(&mut self, _lhs: &Body, _rhs: &Body, _delta: f64) {
+        self.impulse = 0.0;
+    }
+
+    fn solve_velocity(&mut self, lhs: &mut Body, rhs: &mut Body, delta: f64) {
+        let (r1, r2, anchor_delta) = anchors(lhs, &self.local_anchor1, rhs, &self.local_anchor2);
+        let current_length = anchor_delta.len();
+        if current_length == 0.0 {
+            return;
+        }
+
+        let row = Row::linear(anchor_delta * (1.0 / current_length), &r1, &r2);
+        let soft = self.softness.coefficients(delta);
+        let bias = (current_length - self.length) * soft.bias_rate;
+        let impulse = row.impulse(lhs, rhs, bias, &soft, self.impulse);
+
+        self.impulse += impulse;
+        row.apply(lhs, rhs, impulse);
+    }
+
+    fn is_position_based(&self) -> bool {
+        true
+    }
+
+    fn solve_position(&mut self, lhs: &mut Body, rhs: &mut Body, delta: f64) {
+        let (_, _, anchor_delta) = anchors(lhs, &self.local_anchor1, rhs, &self.local_anchor2);
+        let current_length = anchor_delta.len();
+        if current_length == 0.0 {
+            return;
+        }
+
+        let normal = anchor_delta * (1.0 / current_length);
+        let inverse_mass = lhs.get_mass().get_inverse() + rhs.get_mass().get_inverse();
+        let compliance = self.softness.compliance(inverse_mass);
+        let mut lambda = self.impulse * delta;
+        let error = current_length - self.length;
+        project_distance(lhs, rhs, &normal, error, compliance, &mut lambda, delta);
+        self.impulse = lambda / delta;
+    }
+
+    fn applied_impulse(&self) -> f64 {
+        self.impulse.abs()
+    }
+}
+
+// Only keeps the anchors from getting further apart than `max_length`
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RopeJoint {
+    pub entity1: Entity,
+    pub entity2: Entity,
+    pub local_anchor1: Vec2,
+    pub local_anchor2: Vec2,
+    pub max_length: f64,
+    pub softness: Softness,
+    impulse: f64,
+}
+
+impl RopeJoint {
+    pub fn new(entity1: Entity, entity2: Entity, max_length: f64) -> Self {
+        Self {
+            entity1,
+            entity2,
+            local_anchor1: Vec2::new(),
+            local_anchor2: Vec2::new(),
+            max_length,
+            softness: Softness::rigid(),
+            impulse: 0.0,
+        }
+    }
+}
+
+impl Constraint for RopeJoint {
+    fn get_first_entity(&self) -> Entity {
+        self.entity1
+    }
+
+    fn get_second_entity(&self) -> Entity {
+        self.entity2
+    }
+
+    fn prepare(&mut self, _lhs: &Body, _rhs: &Body, _delta: f64) {
+        self.impulse = 0.0;
+    }
+
+    fn solve_velocity(&mut self, lhs: &mut Body, rhs: &mut Body, delta: f64) {
+        let (r1, r2, anchor_delta) = anchors(lhs, &self.local_anchor1, rhs, &self.local_anchor2);
+        let current_length = anchor_delta.len();
+        if current_length == 0.0 {
+            return;
+        }
+
+        let row = Row::linear(anchor_delta * (1.0 / current_length), &r1, &r2);
+        let slack = current_length - self.max_length;
+        let (bias, soft) = if slack < 0.0 {
+            // Slack rope, the anchors may approach each other until it is taut
+            (slack / delta, SoftCoefficients::HARD)
+        } else {
+            let soft = self.softness.coefficients(delta);
+            (slack * soft.bias_rate, soft)
+        };
+        let impulse = row.impulse(lhs, rhs, bias, &soft, self.impulse);
+
+        // A rope can only pull
+        let previous_impulse = self.impulse;
+        self.impulse = (previous_impulse + impulse).min(0.0);
+        row.apply(lhs, rhs, self.impulse - previous_impulse);
+    }
+
+    fn is_position_based(&self) -> bool {
+        true
+    }
+
+    fn solve_position(&mut self, lhs: &mut Body, rhs: &mut Body, delta: f64) {
+        let (_, _, anchor_delta) = anchors(lhs, &self.local_anchor1, rhs, &self.local_anchor2);
+        let current_length = anchor_delta.len();
+        if current_length <= self.max_length {
+            return;
+        }
+
+        let normal = anchor_delta * (1.0 / current_length);
+        let inverse_mass = lhs.get_mass().get_inverse() + rhs.get_mass().get_inverse();
+        let compliance = self.softness.compliance(inverse_mass);
+        let mut lambda = self.impulse * delta;
+        let error = current_length - self.max_length;
+        project_distance(lhs, rhs, &normal, error, compliance, &mut lambda, delta);
+        self.impulse = lambda / delta;
+    }
+
+    fn applied_impulse(&self) -> f64 {
+        self.impulse.abs()
+    }
+}
+
+// Keeps the anchors on top of each other and the relative angle at `reference_angle`,
+// so both bodies move as one
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WeldJoint {
+    pub entity1: Entity,
+    pub entity2: Entity,
+    pub local_anchor1: Vec2,
+    pub local_anchor2: Vec2,
+    pub reference_angle: f64,
+    pub softness: Softness,
+    impulse: Vec2,
+    angular_impulse: f64,
+}
+
+impl WeldJoint {
+    // Keeps the second body at `offset` from the first one
+    pub fn new(entity1: Entity, entity2: Entity, offset: Vec2) -> Self {
+        Self {
+            entity1,
+            entity2,
+            local_anchor1: offset,
+            local_anchor2: Vec2::new(),
+            reference_angle: 0.0,
+            softness: Softness::rigid(),
+            impulse: Vec2::new(),
+            angular_impulse: 0.0,
+        }
+    }
+}
+
+impl Constraint for WeldJoint {
+    fn get_first_entity(&self) -> Entity {
+        self.entity1
+    }
+
+    fn get_second_entity(&self) -> Entity {
+        self.entity2
+    }
+
+    fn prepare(&mut self, _lhs: &Body, _rhs: &Body, _delta: f64) {
+        self.impulse = Vec2::new();
+        self.angular_impulse = 0.0;
+    }
+
+    fn solve_velocity(&mut self, lhs: &mut Body, rhs: &mut Body, delta: f64) {
+        let soft = self.softness.coefficients(delta);
+
+        let row = Row::angular();
+        let angle_error = rhs.angle - lhs.angle - self.reference_angle;
+        let bias = angle_error * soft.bias_rate;
+        let impulse = row.impulse(lhs, rhs, bias, &soft, self.angular_impulse);
+        self.angular_impulse += impulse;
+        row.apply(lhs, rhs, impulse);
+
+        let anchors = anchors(lhs, &self.local_anchor1, rhs, &self.local_anchor2);
+        solve_point(lhs, rhs, anchors, &soft, &mut self.impulse);
+    }
+
+    fn applied_impulse(&self) -> f64 {
+        self.impulse.len()
+    }
+}
+
+// Hinge, the bodies share the anchor point but rotate freely around it
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RevoluteJoint {
+    pub entity1: Entity,
+    pub entity2: Entity,
+    pub local_anchor1: Vec2,
+    pub local_anchor2: Vec2,
+    // Relative angle which counts as zero for the limits
+    pub reference_angle: f64,
+    pub enable_limit: bool,
+    pub lower_angle: f64,
+    pub upper_angle: f64,
+    pub enable_motor: bool,
+    // Relative angular velocity the motor drives towards
+    pub motor_speed: f64,
+    pub max_motor_torque: f64,
+    pub softness: Softness,
+    impulse: Vec2,
+    motor_impulse: f64,
+    lower_impulse: f64,
+    upper_impulse: f64,
+}
+
+impl RevoluteJoint {
+    pub fn new(entity1: Entity, entity2: Entity, local_anchor1: Vec2, local_anchor2: Vec2) -> Self {
+        Self {
+            entity1,
+            entity2,
+            local_anchor1,
+            local_anchor2,
+            reference_angle: 0.0,
+            enable_limit: false,
+            lower_angle: 0.0,
+            upper_angle: 0.0,
+            enable_motor: false,
+            motor_speed: 0.0,
+            max_motor_torque: 0.0,
+            softness: Softness::rigid(),
+            impulse: Vec2::new(),
+            motor_impulse: 0.0,
+            lower_impulse: 0.0,
+            upper_impulse: 0.0,
+        }
+    }
+
+    pub fn angle(&self, lhs: &Body, rhs: &Body) -> f64 {
+        rhs.angle - lhs.angle - self.reference_angle
+    }
+
+    pub const fn motor_impulse(&self) -> f64 {
+        self.motor_impulse
+    }
+}
+
+impl Constraint for RevoluteJoint {
+    fn get_first_entity(&self) -> Entity {
+        self.entity1
+    }
+
+    fn get_second_entity(&self) -> Entity {
+        self.entity2
+    }
+
+    fn prepare(&mut self, _lhs: &Body, _rhs: &Body, _delta: f64) {
+        self.impulse = Vec2::new();
+        self.motor_impulse = 0.0;
+        self.lower_impulse = 0.0;
+        self.upper_impulse = 0.0;
+    }
+
+    fn solve_velocity(&mut self, lhs: &mut Body, rhs: &mut Body, delta: f64) {
+        let row = Row::angular();
+
+        if self.enable_motor {
+            let max_impulse = self.max_motor_torque * delta;
+            row.solve_motor(lhs, rhs, self.motor_speed, max_impulse, &mut self.motor_impulse);
+        }
+
+        if self.enable_limit {
+            let angle = self.angle(lhs, rhs);
+            row.solve_limit(lhs, rhs, angle - self.lower_angle, delta, &mut self.lower_impulse);
+            row.negated()
+                .solve_limit(lhs, rhs, self.upper_angle - angle, delta, &mut self.upper_impulse);
+        }
+
+        let soft = self.softness.coefficients(delta);
+        let anchors = anchors(lhs, &self.local_anchor1, rhs, &self.local_anchor2);
+        solve_point(lhs, rhs, anchors, &soft, &mut self.impulse);
+    }
+
+    fn applied_impulse(&self) -> f64 {
+        self.impulse.len()
+    }
+}
+
+// Slider, the second anchor may only move along `local_axis` of the first body
+// and the bodies may not rotate against each other
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PrismaticJoint {
+    pub entity1: Entity,
+    pub entity2: Entity,
+    pub local_anchor1: Vec2,
+    pub local_anchor2: Vec2,
+    pub local_axis: Vec2,
+    pub reference_angle: f64,
+    pub enable_limit: bool,
+    pub lower_translation: f64,
+    pub upper_translation: f64,
+    pub enable_motor: bool,
+    pub motor_speed: f64,
+    pub max_motor_force: f64,
+    pub softness: Softness,
+    impulse: f64,
+    angular_impulse: f64,
+    motor_impulse: f64,
+    lower_impulse: f64,
+    upper_impulse: f64,
+}
+
+impl PrismaticJoint {
+    pub fn new(entity1: Entity, entity2: Entity, local_axis: Vec2) -> Self {
+        let mut local_axis = local_axis;
+        local_axis.normalize();
+
+        Self {
+            entity1,
+            entity2,
+            local_anchor1: Vec2::new(),
+            local_anchor2: Vec2::new(),
+            local_axis,
+            reference_angle: 0.0,
+            enable_limit: false,
+            lower_translation: 0.0,
+            upper_translation: 0.0,
+            enable_motor: false,
+            motor_speed: 0.0,
+            max_motor_force: 0.0,
+            softness: Softness::rigid(),
+            impulse: 0.0,
+            angular_impulse: 0.0,
+            motor_impulse: 0.0,
+            lower_impulse: 0.0,
+            upper_impulse: 0.0,
+        }
+    }
+
+    pub fn translation(&self, lhs: &Body, rhs: &Body) -> f64 {
+        let (_, _, anchor_delta) = anchors(lhs, &self.local_anchor1, rhs, &self.local_anchor2);
+        self.local_axis.rotated(lhs.angle).scalar_product(&anchor_delta)
+    }
+
+    pub const fn motor_impulse(&self) -> f64 {
+        self.motor_impulse
+    }
+}
+
+impl Constraint for PrismaticJoint {
+    fn get_first_entity(&self) -> Entity {
+        self.entity1
+    }
+
+    fn get_second_entity(&self) -> Entity {
+        self.entity2
+    }
+
+    fn prepare(&mut self, _lhs: &Body, _rhs: &Body, _delta: f64) {
+        self.impulse = 0.0;
+        self.angular_impulse = 0.0;
+        self.motor_impulse = 0.0;
+        self.lower_impulse = 0.0;
+        self.upper_impulse = 0.0;
+    }
+
+    fn solve_velocity(&mut self, lhs: &mut Body, rhs: &mut Body, delta: f64) {
+        let (r1, r2, anchor_delta) = anchors(lhs, &self.local_anchor1, rhs, &self.local_anchor2);
+        let axis = self.local_axis.rotated(lhs.angle);
+        // The first body is pushed where the axis passes the second anchor
+        let lever = anchor_delta + r1;
+
+        let axis_row = Row::linear(axis, &lever, &r2);
+        if self.enable_motor {
+            let max_impulse = self.max_motor_force * delta;
+            axis_row.solve_motor(lhs, rhs, self.motor_speed, max_impulse, &mut self.motor_impulse);
+        }
+
+        if self.enable_limit {
+            let translation = axis.scalar_product(&anchor_delta);
+            let lower_distance = translation - self.lower_translation;
+            let upper_distance = self.upper_translation - translation;
+            axis_row.solve_limit(lhs, rhs, lower_distance, delta, &mut self.lower_impulse);
+            axis_row
+                .negated()
+                .solve_limit(lhs, rhs, upper_distance, delta, &mut self.upper_impulse);
+        }
+
+        let soft = self.softness.coefficients(delta);
+
+        let row = Row::angular();
+        let angle_error = rhs.angle - lhs.angle - self.reference_angle;
+        let bias = angle_error * soft.bias_rate;
+        let impulse = row.impulse(lhs, rhs, bias, &soft, self.angular_impulse);
+        self.angular_impulse += impulse;
+        row.apply(lhs, rhs, impulse);
+
+        let perpendicular = axis.perpendicular();
+        let row = Row::linear(perpendicular, &lever, &r2);
+        let error = perpendicular.scalar_product(&anchor_delta);
+        let impulse = row.impulse(lhs, rhs, error * soft.bias_rate, &soft, self.impulse);
+        self.impulse += impulse;
+        row.apply(lhs, rhs, impulse);
+    }
+
+    fn applied_impulse(&self) -> f64 {
+        self.impulse.abs()
+    }
+}
+
+// Keeps the area of the polygon through the bodies, in counter clockwise order, at
+// `rest_area`. Only solved by the XPBD solver
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AreaConstraint {
+    pub entities: Vec<Entity>,
+    pub rest_area: f64,
+    pub compliance: f64,
+    lambda: f64,
+}
+
+impl AreaConstraint {
+    pub fn new(entities: Vec<Entity>, rest_area: f64) -> Self {
+        Self {
+            entities,
+            rest_area,
+            compliance: 0.0,
+            lambda: 0.0,
+        }
+    }
+
+    pub fn area(positions: &[Vec2]) -> f64 {
+        let next = positions.iter().cycle().skip(1);
+        positions.iter().zip(next).map(|(lhs, rhs)| lhs.cross(rhs)).sum::<f64>() / 2.0
+    }
+
+    pub fn prepare(&mut self) {
+        self.lambda = 0.0;
+    }
+
+    pub fn applied_impulse(&self) -> f64 {
+        self.lambda.abs()
+    }
+
+    // `bodies` in the same order as `entities`
+    pub fn solve_position(&mut self, bodies: &mut [Body], delta: f64) {
+        let count = bodies.len();
+        if count < 3 {
+            return;
+        }
+
+        let positions: Vec<Vec2> = bodies.iter().map(|body| body.position).collect();
+        let error = Self::area(&positions) - self.rest_area;
+        let gradients: Vec<Vec2> = (0..count)
+            .map(|index| {
+                let previous = bodies[(index + count - 1) % count].position;
+                let next = bodies[(index + 1) % count].position;
+                (next - previous).perpendicular() * -0.5
+            })
+            .collect();
+
+        let scaled_compliance = self.compliance / (delta * delta);
+        let denominator = bodies
+            .iter()
+            .zip(&gradients)
+            .map(|(body, gradient)| body.get_mass().get_inverse() * gradient.len_squared())
+            .sum::<f64>()
+            + scaled_compliance;
+        if denominator <= 0.0 {
+            return;
+        }
+
+        let delta_lambda = (-error - scaled_compliance * self.lambda) / denominator;
+        self.lambda += delta_lambda;
+        for (body, gradient) in bodies.iter_mut().zip(&gradients) {
+            body.position += *gradient * (delta_lambda * body.get_mass().get_inverse());
+        }
+    }
+}
+
+// Pushes two touching bodies apart along the contact normal, which points from `rhs`
+// towards `lhs`. The XPBD solver creates one for every contact of a substep
+#[derive(Clone, Copy, Debug)]
+pub struct ContactConstraint {
+    pub normal: Vec2,
+    pub compliance: f64,
+    pub restitution: f64,
+    penetration_depth: f64,
+    // Offset of the bodies along the normal when the contact was found
+    separation: f64,
+    // Approaching speed before the substep, the bounce is based on it
+    normal_velocity: f64,
+    lambda: f64,
+}
+
+impl ContactConstraint {
+    pub fn new(lhs: &Body, rhs: &Body, collision: &CollisionEvent, compliance: f64) -> Self {
+        let normal = collision.normal;
+        Self {
+            normal,
+            compliance,
+            restitution: 0.0,
+            penetration_depth: collision.penetration_depth,
+            separation: (lhs.position - rhs.position).scalar_product(&normal),
+            normal_velocity: (lhs.velocity - rhs.velocity).scalar_product(&normal),
+            lambda: 0.0,
+        }
+    }
+
+    pub fn solve_position(&mut self, lhs: &mut Body, rhs: &mut Body, delta: f64) {
+        // Whatever the bodies moved apart since the contact was found no longer overlaps
+        let moved = (lhs.position - rhs.position).scalar_product(&self.normal) - self.separation;
+        let depth = self.penetration_depth - moved;
+        if depth <= 0.0 {
+            return;
+        }
+
+        project_distance(lhs, rhs, &self.normal, depth, self.compliance, &mut self.lambda, delta);
+    }
+
+    // Replaces the normal velocity the projection left behind with the bounce
+    pub fn solve_velocity(&self, lhs: &mut Body, rhs: &mut Body, delta: f64) {
+        if self.lambda == 0.0 {
+            return;
+        }
+
+        let (lhs_inverse_mass, rhs_inverse_mass) =
+            (lhs.get_mass().get_inverse(), rhs.get_mass().get_inverse());
+        let inverse_mass = lhs_inverse_mass + rhs_inverse_mass;
+        if inverse_mass <= 0.0 {
+            return;
+        }
+
+        // Resting bodies only approach with what the forces added during the substep,
+        // bouncing that back would keep them jittering
+        let acceleration = (lhs.acceleration - rhs.acceleration).scalar_product(&self.normal);
+        let restitution = if -self.normal_velocity > 2.0 * acceleration.abs() * delta {
+            self.restitution
+        } else {
+            0.0
+        };
+
+        let normal_velocity = (lhs.velocity - rhs.velocity).scalar_product(&self.normal);
+        let target = (-restitution * self.normal_velocity).max(0.0);
+        let impulse = (target - normal_velocity) / inverse_mass;
+        lhs.velocity += self.normal * (impulse * lhs_inverse_mass);
+        rhs.velocity -= self.normal * (impulse * rhs_inverse_mass);
+    }
+
+    pub fn applied_impulse(&self) -> f64 {
+        self.lambda.abs()
+    }
+}
+
+// Drags a point of the body towards `target`, e.g. to grab it with the mouse. The
+// force is capped at `max_force`, so the body can still get stuck behind others
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TargetJoint {
+    pub entity: Entity,
+    pub local_anchor: Vec2,
+    pub target: Vec2,
+    pub max_force: f64,
+    pub softness: Softness,
+    impulse: Vec2,
+}
+
+impl TargetJoint {
+    pub fn new(entity: Entity, target: Vec2, max_force: f64) -> Self {
+        Self {
+            entity,
+            local_anchor: Vec2::new(),
+            target,
+            max_force,
+            softness: Softness::new(5.0, 0.7),
+            impulse: Vec2::new(),
+        }
+    }
+
+    // Grabs the body at `point`, which starts out as the target
+    pub fn at_point(entity: Entity, body: &Body, point: Vec2, max_force: f64) -> Self {
+        Self {
+            local_anchor: (point - body.position).rotated(-body.angle),
+            ..Self::new(entity, point, max_force)
+        }
+    }
+
+    // Stands in for the other end of the joint, it never moves
+    fn ground(&self) -> Body {
+        Body::new(Shape::Circle { radius: 0.0 }, self.target, Mass::Infinity)
+    }
+}
+
+impl UnaryConstraint for TargetJoint {
+    fn get_entity(&self) -> Entity {
+        self.entity
+    }
+
+    fn prepare(&mut self, _body: &Body, _delta: f64) {
+        self.impulse = Vec2::new();
+    }
+
+    fn solve_velocity(&mut self, body: &mut Body, delta: f64) {
+        let soft = self.softness.coefficients(delta);
+        let mut ground = self.ground();
+        let anchors = anchors(&ground, &Vec2::new(), body, &self.local_anchor);
+        let (r1, r2, _) = anchors;
+        solve_point(&mut ground, body, anchors, &soft, &mut self.impulse);
+
+        let max_impulse = self.max_force * delta;
+        if self.impulse.len() > max_impulse {
+            let mut clamped = self.impulse;
+            clamped.set_len(max_impulse);
+            let correction = clamped - self.impulse;
+            Row::linear(Vec2::new_at(1.0, 0.0), &r1, &r2).apply(&mut ground, body, correction.x);
+            Row::linear(Vec2::new_at(0.0, 1.0), &r1, &r2).apply(&mut ground, body, correction.y);
+            self.impulse = clamped;
+        }
+    }
+
+    fn applied_impulse(&self) -> f64 {
+        self.impulse.len()
+    }
+}
+
+#[cfg(feature = "serde")]
+tagged_registry!(ConstraintRecord: dyn Constraint {
+    FakeSpring,
+    DampedSpring,
+    BungeeSpring,
+    DistanceJoint,
+    RopeJoint,
+    WeldJoint,
+    RevoluteJoint,
+    PrismaticJoint,
+});
+
+#[cfg(feature = "serde")]
+tagged_registry!(UnaryConstraintRecord: dyn UnaryConstraint { TargetJoint });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::World;
+
+    const DELTA: f64 = 1.0 / 60.0;
+
+    // Handles for constraints which get solved on bodies outside of a world
+    fn entities() -> (Entity, Entity) {
+        let mut world = World::new();
+        let body = Body::new(Shape::Circle { radius: 1.0 }, Vec2::new(), Mass::Infinity);
+        (world.add(body), world.add(body))
+    }
+
+    fn solve(constraint: &mut dyn Constraint, lhs: &mut Body, rhs: &mut Body) {
+        constraint.prepare(lhs, rhs, DELTA);
+        for _ in 0..8 {
+            constraint.solve_velocity(lhs, rhs, DELTA);
+        }
+    }
+
+    #[test]
+    fn test_distance_joint_pendulum() {
+        let mut world = World::new();
+        let anchor = world.add(Body::new(
+            Shape::Square { edge_length: 1.0 },
+            Vec2::new(),
+            Mass::Infinity,
+        ));
+        let bob = world.add(Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(10.0, 0.0),
+            Mass::Value(1.0),
+        ));
+        world.add_constraint(Box::new(DistanceJoint::new(anchor, bob, 10.0)));
+
+        for _ in 0..240 {
+            world.apply_force(bob, Vec2::new_at(0.0, -100.0));
+            world.update(std::time::Duration::from_secs_f64(DELTA));
+
+            let length = world.body(bob).unwrap().position.len();
+            assert!((length - 10.0).abs() < 0.5, "{length}");
+        }
+
+        // It swung down instead of falling
+        assert!(world.body(bob).unwrap().position.y < -5.0);
+    }
+
+    #[test]
+    fn test_rope_joint_only_pulls() {
+        let mut anchor = Body::new(Shape::Circle { radius: 1.0 }, Vec2::new(), Mass::Infinity);
+        let mut body = Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(5.0, 0.0),
+            Mass::Value(1.0),
+        );
+        body.velocity = Vec2::new_at(10.0, 0.0);
+
+        let (first, second) = entities();
+        let mut rope = RopeJoint::new(first, second, 10.0);
+        solve(&mut rope, &mut anchor, &mut body);
+        assert_eq!(Vec2::new_at(10.0, 0.0), body.velocity);
+        assert_eq!(0.0, rope.applied_impulse());
+
+        body.position = Vec2::new_at(12.0, 0.0);
+        solve(&mut rope, &mut anchor, &mut body);
+        assert!(body.velocity.x < 0.0);
+        assert!(rope.applied_impulse() > 0.0);
+        assert_eq!(Vec2::new(), anchor.velocity);
+    }
+
+    #[test]
+    fn test_weld_joint_moves_as_one() {
+        let mut lhs = Body::new(Shape::Circle { radius: 1.0 }, Vec2::new(), Mass::Value(1.0));
+        let mut rhs = Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(3.0, 0.0),
+            Mass::Value(1.0),
+        );
+        lhs.velocity = Vec2::new_at(2.0, 0.0);
+
+        let (first, second) = entities();
+        let mut weld = WeldJoint::new(first, second, Vec2::new_at(3.0, 0.0));
+        solve(&mut weld, &mut lhs, &mut rhs);
+
+        assert_eq!(Vec2::new_at(1.0, 0.0), lhs.velocity);
+        assert_eq!(Vec2::new_at(1.0, 0.0), rhs.velocity);
+        assert_eq!(1.0, weld.applied_impulse());
+    }
+
+    #[test]
+    fn test_revolute_joint_motor_and_limits() {
+        let mut anchor = Body::new(Shape::Square { edge_length: 1.0 }, Vec2::new(), Mass::Infinity);
+        let mut wheel = Body::new(Shape::Circle { radius: 1.0 }, Vec2::new(), Mass::Value(1.0));
+
+        let (first, second) = entities();
+        let mut hinge = RevoluteJoint::new(first, second, Vec2::new(), Vec2::new());
+        hinge.enable_motor = true;
+        hinge.motor_speed = 2.0;
+        hinge.max_motor_torque = 1000.0;
+        solve(&mut hinge, &mut anchor, &mut wheel);
+        assert!((wheel.angular_velocity - 2.0).abs() < 1.0e-9);
+        assert!(hinge.motor_impulse() > 0.0);
+
+        // A weak motor can not reach its speed within one step
+        wheel.angular_velocity = 0.0;
+        hinge.max_motor_torque = 1.0;
+        solve(&mut hinge, &mut anchor, &mut wheel);
+        assert!(wheel.angular_velocity < 1.0);
+
+        hinge.enable_motor = false;
+        hinge.enable_limit = true;
+        hinge.lower_angle = -0.5;
+        hinge.upper_angle = 0.5;
+        wheel.angle = 0.6;
+        wheel.angular_velocity = 1.0;
+        solve(&mut hinge, &mut anchor, &mut wheel);
+        assert!(wheel.angular_velocity < 0.0);
+    }
+
+    #[test]
+    fn test_prismatic_joint_slides_along_axis() {
+        let mut rail = Body::new(Shape::Square { edge_length: 1.0 }, Vec2::new(), Mass::Infinity);
+        let mut slider = Body::new(Shape::Circle { radius: 1.0 }, Vec2::new(), Mass::Value(1.0));
+        slider.velocity = Vec2::new_at(3.0, 4.0);
+        slider.angular_velocity = 1.0;
+
+        let (first, second) = entities();
+        let mut joint = PrismaticJoint::new(first, second, Vec2::new_at(2.0, 0.0));
+        solve(&mut joint, &mut rail, &mut slider);
+        assert!((slider.velocity - Vec2::new_at(3.0, 0.0)).len() < 1.0e-9);
+        assert!(slider.angular_velocity.abs() < 1.0e-9);
+
+        joint.enable_limit = true;
+        joint.lower_translation = -1.0;
+        joint.upper_translation = 1.0;
+        slider.position = Vec2::new_at(1.2, 0.0);
+        solve(&mut joint, &mut rail, &mut slider);
+        assert!(slider.velocity.x < 0.0);
+        assert!((joint.translation(&rail, &slider) - 1.2).abs() < 1.0e-9);
+    }
+
+    #[test]
+    fn test_springs_act_on_both_bodies() {
+        let mut lhs = Body::new(Shape::Circle { radius: 1.0 }, Vec2::new(), Mass::Value(1.0));
+        let mut rhs = Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(20.0, 0.0),
+            Mass::Value(3.0),
+        );
+        rhs.velocity = Vec2::new_at(0.0, 5.0);
+
+        let (first, second) = entities();
+        BungeeSpring::new(first, second, 10.0).process(&mut lhs, &mut rhs, DELTA);
+        assert!(lhs.force.x > 0.0);
+        assert_eq!(Vec2::new(), lhs.force + rhs.force);
+
+        lhs.force = Vec2::new();
+        rhs.force = Vec2::new();
+        FakeSpring::new(first, second, 1.0).process(&mut lhs, &mut rhs, DELTA);
+        assert!(lhs.force.x > 0.0);
+        assert_eq!(Vec2::new(), lhs.force + rhs.force);
+
+        // Against an immovable body the spring is unchanged for the movable one
+        let mut anchor = Body::new(Shape::Circle { radius: 1.0 }, Vec2::new(), Mass::Infinity);
+        let mut body = Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(20.0, 0.0),
+            Mass::Value(3.0),
+        );
+        FakeSpring::new(first, second, 1.0).process(&mut body, &mut anchor, DELTA);
+        assert!(body.force.x < 0.0);
+        assert_eq!(body.force, -anchor.force);
+
+        // Stepped in a world the pair keeps its momentum
+        for body in [&mut lhs, &mut rhs] {
+            body.force = Vec2::new();
+            body.friction = 1.0;
+        }
+        let mut world = World::new();
+        let lhs = world.add(lhs);
+        let rhs = world.add(rhs);
+        world.add_constraint(Box::new(FakeSpring::new(lhs, rhs, 1.0)));
+        world.update(std::time::Duration::from_secs_f64(DELTA));
+        let (lhs, rhs) = (world.body(lhs).unwrap(), world.body(rhs).unwrap());
+        assert!(lhs.velocity.x > 0.0);
+        let momentum = lhs.velocity * 1.0 + rhs.velocity * 3.0;
+        assert!((momentum - Vec2::new_at(0.0, 15.0)).len() < 1.0e-9, "{momentum:?}");
+    }
+
+    #[test]
+    fn test_damped_oscillation_regimes() {
+        // Against a fine explicit integration of the same oscillator
+        for damping in [0.0, 2.0, 20.0, 60.0] {
+            let (stiffness, time) = (100.0, 0.5);
+            let (mut x, mut v) = (1.0, -3.0);
+            let steps = 200_000;
+            for _ in 0..steps {
+                v += (-stiffness * x - damping * v) * (time / steps as f64);
+                x += v * (time / steps as f64);
+            }
+
+            let [[a, b], [c, d]] = damped_oscillation(stiffness, damping, time);
+            assert!((a * 1.0 + b * -3.0 - x).abs() < 1.0e-3, "{damping}: {x}");
+            assert!((c * 1.0 + d * -3.0 - v).abs() < 1.0e-2, "{damping}: {v}");
+        }
+
+        // Overdamped used to produce NaN
+        let mut lhs = Body::new(Shape::Circle { radius: 1.0 }, Vec2::new(), Mass::Value(1.0));
+        let mut rhs = Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(5.0, 0.0),
+            Mass::Value(1.0),
+        );
+        let (first, second) = entities();
+        FakeSpring::new(first, second, 50.0).process(&mut lhs, &mut rhs, DELTA);
+        assert!(lhs.force.x.is_finite() && lhs.force.x > 0.0);
+    }
+
+    // Energy and extension after every step of two bodies stretched apart by 5
+    fn spring_energy(damping: f64) -> Vec<(f64, f64)> {
+        let mut world = World::new();
+        let mut lhs = Body::new(Shape::Circle { radius: 1.0 }, Vec2::new(), Mass::Value(1.0));
+        let mut rhs = Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(15.0, 0.0),
+            Mass::Value(2.0),
+        );
+        lhs.friction = 1.0;
+        rhs.friction = 1.0;
+        let (lhs, rhs) = (world.add(lhs), world.add(rhs));
+        let spring = DampedSpring::new(lhs, rhs, 10.0, 100.0, damping);
+        world.add_constraint(Box::new(spring));
+
+        (0..240)
+            .map(|_| {
+                world.update(std::time::Duration::from_secs_f64(DELTA));
+                let (lhs, rhs) = (world.body(lhs).unwrap(), world.body(rhs).unwrap());
+                let momentum = lhs.velocity * 1.0 + rhs.velocity * 2.0;
+                assert!(momentum.len() < 1.0e-9);
+
+                let extension = (rhs.position - lhs.position).len() - 10.0;
+                let kinetic =
+                    0.5 * lhs.velocity.len_squared() + 0.5 * 2.0 * rhs.velocity.len_squared();
+                (kinetic + 0.5 * 100.0 * (extension * extension), extension)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_damped_spring_energy_decays() {
+        let reduced_mass = 2.0 / 3.0;
+        let critical = DampedSpring::critical_damping(100.0, reduced_mass);
+        let initial_energy = 0.5 * 100.0 * 25.0;
+        // Roughly one period of the undamped spring
+        let window_energy = |energies: &[(f64, f64)], window: usize| {
+            energies[window * 30..(window + 1) * 30]
+                .iter()
+                .map(|(energy, _)| energy)
+                .sum::<f64>()
+                / 30.0
+        };
+
+        let mut final_energies = vec![];
+        for damping in [0.0, 0.2 * critical, critical, 5.0 * critical] {
+            let energies = spring_energy(damping);
+
+            // The extension follows the exact solution in every regime
+            for (step, (_, extension)) in energies.iter().enumerate() {
+                let time = (step + 1) as f64 * DELTA;
+                let [[a, _], _] = damped_oscillation(
+                    100.0 / reduced_mass,
+                    damping / reduced_mass,
+                    time,
+                );
+                assert!((a * 5.0 - extension).abs() < 1.0e-4, "{damping}: {extension}");
+            }
+
+            // Measured with the average velocities over the steps, so it wobbles a little
+            for (energy, _) in &energies {
+                assert!(*energy < 1.1 * initial_energy, "{damping}: {energy}");
+            }
+            if damping == 0.0 {
+                let ratio = window_energy(&energies, 7) / window_energy(&energies, 0);
+                assert!((ratio - 1.0).abs() < 0.05, "{ratio}");
+            } else {
+                for window in 1..8 {
+                    let previous = window_energy(&energies, window - 1);
+                    assert!(window_energy(&energies, window) < previous, "{damping}");
+                }
+            }
+            if damping >= critical {
+                // No overshoot past the rest length
+                assert!(energies.iter().all(|(_, extension)| *extension > -1.0e-9));
+            }
+            final_energies.push(energies.last().unwrap().0);
+        }
+
+        // The overdamped spring creeps back slower than the critically damped one
+        assert!(final_energies[1] < 1.0e-3 * initial_energy);
+        assert!(final_energies[2] < final_energies[1]);
+        assert!(final_energies[3] > final_energies[2]);
+    }
+
+    #[test]
+    fn test_contact_constraint() {
+        let ground = Body::new(Shape::Square { edge_length: 2.0 }, Vec2::new(), Mass::Infinity);
+        let mut ball = Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(0.0, 1.5),
+            Mass::Value(1.0),
+        );
+        ball.velocity = Vec2::new_at(0.0, -3.0);
+        let collision =
+            crate::collision_resolution::detect_collision(&mut ball.clone(), &mut ground.clone())
+                .unwrap();
+        let delta = 0.01;
+
+        let mut rigid = ContactConstraint::new(&ball, &ground, &collision, 0.0);
+        rigid.restitution = 0.5;
+        let (mut lhs, mut rhs) = (ball, ground);
+        rigid.solve_position(&mut lhs, &mut rhs, delta);
+        assert!((lhs.position.y - 2.0).abs() < 1e-9);
+        assert_eq!(Vec2::new(), rhs.position);
+        rigid.solve_velocity(&mut lhs, &mut rhs, delta);
+        assert!((lhs.velocity.y - 1.5).abs() < 1e-9);
+
+        // Half of the overlap stays with a compliance of one per squared step, and the
+        // accumulated multiplier keeps a second projection from pushing any further
+        let mut soft = ContactConstraint::new(&ball, &ground, &collision, delta * delta);
+        let (mut lhs, mut rhs) = (ball, ground);
+        soft.solve_position(&mut lhs, &mut rhs, delta);
+        assert!((lhs.position.y - 1.75).abs() < 1e-9);
+        soft.solve_position(&mut lhs, &mut rhs, delta);
+        assert!((lhs.position.y - 1.75).abs() < 1e-9);
+        assert!((soft.applied_impulse() - 0.25).abs() < 1e-9);
+    }
 }
@@ -0,0 +1,234 @@
+use crate::{
+    bsp::{
+        aabb::Aabb,
+        quadtree::{Quadtree, QuadtreeConfig},
+    },
+    constraint::AsAny,
+    primitive::{Mass, Vec2},
+    world::{Entity, World},
+};
+#[cfg(feature = "serde")]
+use crate::{constraint::tagged_registry, soft_body::Pressure};
+
+pub trait ForceGeneratorClone {
+    fn clone_box(&self) -> Box<dyn ForceGenerator>;
+}
+
+impl<T: ForceGenerator + Clone + 'static> ForceGeneratorClone for T {
+    fn clone_box(&self) -> Box<dyn ForceGenerator> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn ForceGenerator> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+pub trait ForceGenerator: AsAny + ForceGeneratorClone {
+    // Forces to add to the bodies before they get integrated
+    fn forces(&mut self, world: &World, delta: f64) -> Vec<(Entity, Vec2)>;
+}
+
+// Pulls every body of finite mass with the same acceleration
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Gravity {
+    pub acceleration: Vec2,
+}
+
+impl ForceGenerator for Gravity {
+    fn forces(&mut self, world: &World, _delta: f64) -> Vec<(Entity, Vec2)> {
+        world
+            .entities()
+            .filter_map(|(entity, body)| match *body.get_mass() {
+                Mass::Value(mass) => Some((entity, self.acceleration * mass)),
+                Mass::Infinity => None,
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BarnesHutGravity {
+    pub gravitational_constant: f64,
+    // Opening angle, nodes smaller than `theta` times their distance are approximated
+    pub theta: f64,
+    // Avoids the singularity of bodies passing right through each other
+    pub softening: f64,
+    pub config: QuadtreeConfig,
+}
+
+#[derive(Clone, Copy, Default)]
+struct NodeMass {
+    mass: f64,
+    center_of_mass: Vec2,
+}
+
+impl BarnesHutGravity {
+    pub fn new(gravitational_constant: f64, theta: f64) -> Self {
+        Self {
+            gravitational_constant,
+            theta,
+            softening: 1.0,
+            config: QuadtreeConfig::default(),
+        }
+    }
+
+    fn attraction(&self, position: &Vec2, mass: f64, other: &NodeMass) -> Vec2 {
+        let delta = other.center_of_mass - *position;
+        let distance_squared = delta.len_squared() + self.softening * self.softening;
+        let magnitude = self.gravitational_constant * mass * other.mass / distance_squared;
+        delta * (magnitude / distance_squared.sqrt())
+    }
+
+    // Aggregates mass and center of mass bottom up, indexed like the quadtree nodes
+    fn node_masses(quadtree: &Quadtree, masses: &[(Entity, Vec2, f64)]) -> Vec<NodeMass> {
+        let mut node_masses = vec![NodeMass::default(); quadtree.node_count()];
+        let Some(root) = quadtree.root() else {
+            return node_masses;
+        };
+
+        let mut pre_order = vec![];
+        let mut nodes_to_visit = vec![root];
+        while let Some(index) = nodes_to_visit.pop() {
+            pre_order.push(index);
+            nodes_to_visit.extend(quadtree.node(index).children().into_iter().flatten());
+        }
+
+        for index in pre_order.into_iter().rev() {
+            let node = quadtree.node(index);
+            let mut mass = 0.0;
+            let mut weighted_position = Vec2::new();
+
+            for entry in node.entries() {
+                let (_, position, entry_mass) = masses[entry.id];
+                mass += entry_mass;
+                weighted_position += position * entry_mass;
+            }
+
+            for child in node.children().into_iter().flatten() {
+                let child = node_masses[child];
+                mass += child.mass;
+                weighted_position += child.center_of_mass * child.mass;
+            }
+
+            if mass > 0.0 {
+                node_masses[index] = NodeMass {
+                    mass,
+                    center_of_mass: weighted_position * (1.0 / mass),
+                };
+            }
+        }
+
+        node_masses
+    }
+}
+
+impl ForceGenerator for BarnesHutGravity {
+    fn forces(&mut self, world: &World, _delta: f64) -> Vec<(Entity, Vec2)> {
+        // Bodies of infinite mass neither attract nor get attracted
+        let masses: Vec<(Entity, Vec2, f64)> = world
+            .entities()
+            .filter_map(|(entity, body)| match *body.get_mass() {
+                Mass::Value(mass) => Some((entity, body.position, mass)),
+                Mass::Infinity => None,
+            })
+            .collect();
+
+        // Entry ids index into `masses`
+        let mut quadtree = Quadtree::new(self.config);
+        for (index, (_, position, _)) in masses.iter().enumerate() {
+            quadtree.insert(index, Aabb::from_point(position));
+        }
+
+        let node_masses = Self::node_masses(&quadtree, &masses);
+        let Some(root) = quadtree.root() else {
+            return vec![];
+        };
+
+        let mut forces = Vec::with_capacity(masses.len());
+        let mut nodes_to_visit = vec![];
+        for (index, (entity, position, mass)) in masses.iter().enumerate() {
+            let mut force = Vec2::new();
+            nodes_to_visit.clear();
+            nodes_to_visit.push(root);
+
+            while let Some(node_index) = nodes_to_visit.pop() {
+                let node = quadtree.node(node_index);
+                let node_mass = &node_masses[node_index];
+                if node_mass.mass <= 0.0 {
+                    continue;
+                }
+
+                let size = 2.0 * node.half_size();
+                let distance = (node_mass.center_of_mass - *position).len();
+                let offset = *position - node.center();
+                let contains_body =
+                    offset.x.abs() <= node.half_size() && offset.y.abs() <= node.half_size();
+
+                if !contains_body && size < self.theta * distance {
+                    force += self.attraction(position, *mass, node_mass);
+                    continue;
+                }
+
+                for entry in node.entries().iter().filter(|e| e.id != index) {
+                    let (_, other_position, other_mass) = masses[entry.id];
+                    let other = NodeMass {
+                        mass: other_mass,
+                        center_of_mass: other_position,
+                    };
+                    force += self.attraction(position, *mass, &other);
+                }
+
+                nodes_to_visit.extend(node.children().into_iter().flatten());
+            }
+
+            forces.push((*entity, force));
+        }
+
+        forces
+    }
+}
+
+#[cfg(feature = "serde")]
+tagged_registry!(ForceGeneratorRecord: dyn ForceGenerator { Gravity, BarnesHutGravity, Pressure });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitive::{Body, Shape};
+
+    #[test]
+    fn test_matches_direct_summation() {
+        let mut world = World::new();
+        for index in 0..40 {
+            let position = Vec2::new_at((index * 37 % 101) as f64, (index * 53 % 97) as f64);
+            let body = Body::new(
+                Shape::Circle { radius: 0.1 },
+                position,
+                Mass::Value(1.0 + index as f64),
+            );
+            world.add(body);
+        }
+
+        let mut exact = BarnesHutGravity::new(10.0, 0.0);
+        let mut approximated = BarnesHutGravity::new(10.0, 0.5);
+        let exact_forces = exact.forces(&world, 0.0);
+        let approximated_forces = approximated.forces(&world, 0.0);
+
+        let mut total = Vec2::new();
+        for ((entity, exact), (other_entity, approximated)) in
+            exact_forces.iter().zip(&approximated_forces)
+        {
+            assert_eq!(entity, other_entity);
+            assert!((*exact - *approximated).len() <= 0.05 * exact.len() + 1.0e-9);
+            total += *exact;
+        }
+
+        // Without approximation every force has its equal and opposite counterpart
+        assert!(total.len() < 1.0e-9);
+    }
+}
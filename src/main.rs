@@ -1,5 +1,3 @@
-#![feature(get_many_mut)]
-#![feature(map_many_mut)]
 #![allow(dead_code)]
 
 extern crate glutin_window;
@@ -10,16 +8,21 @@ extern crate piston;
 mod bsp;
 mod collision_resolution;
 mod constraint;
+mod force_generator;
+mod math;
 mod primitive;
+mod rope;
+mod scene;
+mod soft_body;
 mod util;
 mod world;
 
-use std::f64::consts::PI;
+use std::error::Error;
 
 use graphics::color::{BLACK, RED, WHITE};
-use primitive::{Body, Mass, Shape, Vec2};
-use rand::Rng;
-use world::{CollisionData, World};
+use primitive::{Shape, Vec2};
+use scene::Scene;
+use world::{CollisionData, World, WorldEvent};
 
 use glutin_window::GlutinWindow as Window;
 use opengl_graphics::{GlGraphics, OpenGL};
@@ -88,26 +91,17 @@ impl App {
     }
 
     fn update(&mut self, args: &UpdateArgs) {
-        let mut force_updates = vec![];
-        for (entity, body) in self.world.entities() {
-            let mass_value = if let Mass::Value(val) = *body.get_mass() {
-                val
-            } else {
-                continue;
-            };
-
-            force_updates.push((*entity, Vec2::new_at(0.0, -300.0 * mass_value)));
-        }
-
-        for (entity, force) in force_updates {
-            self.world.apply_force(entity, force);
-        }
-
-        let collisions = self
+        let events = self
             .world
             .update(std::time::Duration::from_secs_f64(args.dt));
 
-        let collisions_to_collect: Vec<CollisionData> = collisions.into_iter().collect();
+        let collisions_to_collect: Vec<CollisionData> = events
+            .into_iter()
+            .filter_map(|event| match event {
+                WorldEvent::Collision(collision) => Some(collision),
+                WorldEvent::ConstraintBroken { .. } => None,
+            })
+            .collect();
         for collision in collisions_to_collect {
             self.collisions.push(CollisionEffect{event: collision, ttl: std::time::Duration::from_secs_f64(0.05)});
         }
@@ -121,40 +115,35 @@ impl App {
     }
 }
 
-fn main() {
-    let mut world: World = World::new();
-
-    let mut rng = rand::thread_rng();
-    for _ in 0..40 {
-        let x = rng.gen_range(-700.0..700.0);
-        let y = rng.gen_range(-400.0..-200.0);
-        let edge_length = rng.gen_range(20.0..45.0);
-
-        let body = Body::new(
-            Shape::Square { edge_length },
-            Vec2::new_at(x, y),
-            Mass::Infinity,
-        );
-        world.add(body);
-    }
+// `--scene file.ron` replaces the built-in demo scene
+fn load_world() -> Result<World, Box<dyn Error>> {
+    let mut args = std::env::args().skip(1);
+    let scene = match (args.next(), args.next()) {
+        (None, _) => Scene::demo(),
+        (Some(flag), Some(path)) if flag == "--scene" => read_scene(&path)?,
+        _ => return Err("usage: wirt-collision-rs [--scene file.ron]".into()),
+    };
+    Ok(scene.build()?)
+}
 
-    for _ in 0..2000 {
-        let x = rng.gen_range(-1000.0..1000.0);
-        let y = rng.gen_range(500.0..4500.0);
-        let dx = rng.gen_range(-50.0..50.0);
-        let dy = rng.gen_range(-50.0..50.0);
-        let radius = rng.gen_range(5.0..12.0);
-
-        let mut body = Body::new(
-            Shape::Circle { radius },
-            Vec2::new_at(x, y),
-            Mass::Value(PI * radius.powi(2)),
-        );
-
-        body.velocity.x = dx;
-        body.velocity.y = dy;
-        world.add(body);
-    }
+#[cfg(feature = "scene")]
+fn read_scene(path: &str) -> Result<Scene, Box<dyn Error>> {
+    Scene::load(path).map_err(|error| format!("{path}: {error}").into())
+}
+
+#[cfg(not(feature = "scene"))]
+fn read_scene(_path: &str) -> Result<Scene, Box<dyn Error>> {
+    Err("scene files need the `scene` feature".into())
+}
+
+fn main() {
+    let world = match load_world() {
+        Ok(world) => world,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    };
 
     // Change this to OpenGL::V2_1 if not working.
     let opengl = OpenGL::V3_2;
@@ -0,0 +1,355 @@
+// Transcendental functions go through here. With the `deterministic` feature they are computed
+// in software from IEEE 754 arithmetic only, so every platform rounds them the same way instead
+// of depending on its libm. `sqrt` is correctly rounded by IEEE 754 itself and needs no stand-in.
+// Squares are written out as `x * x`, `powi` makes no promise about its rounding
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, LOG2_E, PI};
+
+pub fn sin(x: f64) -> f64 {
+    if cfg!(feature = "deterministic") {
+        soft::sin_cos(x).0
+    } else {
+        x.sin()
+    }
+}
+
+pub fn cos(x: f64) -> f64 {
+    if cfg!(feature = "deterministic") {
+        soft::sin_cos(x).1
+    } else {
+        x.cos()
+    }
+}
+
+pub fn sin_cos(x: f64) -> (f64, f64) {
+    if cfg!(feature = "deterministic") {
+        soft::sin_cos(x)
+    } else {
+        x.sin_cos()
+    }
+}
+
+pub fn exp(x: f64) -> f64 {
+    if cfg!(feature = "deterministic") {
+        soft::exp(x)
+    } else {
+        x.exp()
+    }
+}
+
+pub fn powf(x: f64, y: f64) -> f64 {
+    if cfg!(feature = "deterministic") {
+        soft::powf(x, y)
+    } else {
+        x.powf(y)
+    }
+}
+
+pub fn atan2(y: f64, x: f64) -> f64 {
+    if cfg!(feature = "deterministic") {
+        soft::atan2(y, x)
+    } else {
+        y.atan2(x)
+    }
+}
+
+pub fn hypot(x: f64, y: f64) -> f64 {
+    if cfg!(feature = "deterministic") {
+        (x * x + y * y).sqrt()
+    } else {
+        x.hypot(y)
+    }
+}
+
+// A fused multiply-add falls back to a library call without hardware support, two separately
+// rounded operations don't
+pub fn mul_add(a: f64, b: f64, c: f64) -> f64 {
+    if cfg!(feature = "deterministic") {
+        a * b + c
+    } else {
+        a.mul_add(b, c)
+    }
+}
+
+// Ports of the fdlibm kernels, without the extra precision tricks for huge arguments
+mod soft {
+    use super::*;
+
+    const PIO2_HI: f64 = 1.570_796_326_734_125_6;
+    const PIO2_MID: f64 = 6.077_100_506_303_966e-11;
+    const PIO2_LO: f64 = 2.022_266_248_711_166_5e-21;
+
+    const LN2_HI: f64 = 6.931_471_803_691_238e-1;
+    const LN2_LO: f64 = 1.908_214_929_270_587_7e-10;
+
+    fn kernel_sin(x: f64) -> f64 {
+        const S: [f64; 6] = [
+            -1.666_666_666_666_663_2e-1,
+            8.333_333_333_322_49e-3,
+            -1.984_126_982_985_795e-4,
+            2.755_731_370_707_006_8e-6,
+            -2.505_076_025_340_686_3e-8,
+            1.589_690_995_211_55e-10,
+        ];
+        let z = x * x;
+        let r = S[1] + z * (S[2] + z * (S[3] + z * (S[4] + z * S[5])));
+        x + z * x * (S[0] + z * r)
+    }
+
+    fn kernel_cos(x: f64) -> f64 {
+        const C: [f64; 6] = [
+            4.166_666_666_666_66e-2,
+            -1.388_888_888_887_411e-3,
+            2.480_158_728_947_673e-5,
+            -2.755_731_435_139_066_3e-7,
+            2.087_572_321_298_175e-9,
+            -1.135_964_755_778_819_5e-11,
+        ];
+        let z = x * x;
+        let r = z * (C[0] + z * (C[1] + z * (C[2] + z * (C[3] + z * (C[4] + z * C[5])))));
+        let half_z = 0.5 * z;
+        let w = 1.0 - half_z;
+        w + (((1.0 - w) - half_z) + z * r)
+    }
+
+    pub fn sin_cos(x: f64) -> (f64, f64) {
+        if !x.is_finite() {
+            return (f64::NAN, f64::NAN);
+        }
+
+        // Reduced to [-pi/4, pi/4] and the quadrant
+        let quadrant = (x * (2.0 / PI)).round();
+        let r = x - quadrant * PIO2_HI - quadrant * PIO2_MID - quadrant * PIO2_LO;
+        let (sin, cos) = (kernel_sin(r), kernel_cos(r));
+        match (quadrant as i64).rem_euclid(4) {
+            0 => (sin, cos),
+            1 => (cos, -sin),
+            2 => (-sin, -cos),
+            _ => (-cos, sin),
+        }
+    }
+
+    // Multiplies by 2^n without losing the subnormal range
+    fn scale(mut x: f64, mut n: i32) -> f64 {
+        let two_pow_1023 = f64::from_bits(0x7fe0_0000_0000_0000);
+        let two_pow_minus_969 = f64::from_bits(0x0360_0000_0000_0000);
+        if n > 1023 {
+            x *= two_pow_1023;
+            n = (n - 1023).min(1023);
+        } else if n < -1022 {
+            x *= two_pow_minus_969;
+            n = (n + 969).max(-1022);
+        }
+        x * f64::from_bits(((0x3ff + n) as u64) << 52)
+    }
+
+    pub fn exp(x: f64) -> f64 {
+        const P: [f64; 5] = [
+            1.666_666_666_666_660_2e-1,
+            -2.777_777_777_701_559_3e-3,
+            6.613_756_321_437_934e-5,
+            -1.653_390_220_546_525_2e-6,
+            4.138_136_797_057_238_5e-8,
+        ];
+        if x.is_nan() {
+            return x;
+        }
+        if x > 709.782_712_893_384 {
+            return f64::INFINITY;
+        }
+        if x < -745.133_219_101_941_1 {
+            return 0.0;
+        }
+
+        // x = k ln2 + r with |r| <= ln2 / 2
+        let k = (x * LOG2_E).round();
+        let hi = x - k * LN2_HI;
+        let lo = k * LN2_LO;
+        let r = hi - lo;
+        let z = r * r;
+        let c = r - z * (P[0] + z * (P[1] + z * (P[2] + z * (P[3] + z * P[4]))));
+        let y = 1.0 - ((lo - (r * c) / (2.0 - c)) - hi);
+        scale(y, k as i32)
+    }
+
+    pub fn ln(x: f64) -> f64 {
+        const LG: [f64; 7] = [
+            6.666_666_666_666_735e-1,
+            3.999_999_999_940_942e-1,
+            2.857_142_874_366_239e-1,
+            2.222_219_843_214_978_4e-1,
+            1.818_357_216_161_805e-1,
+            1.531_383_769_920_937_3e-1,
+            1.479_819_860_511_658_6e-1,
+        ];
+        if x.is_nan() || x < 0.0 {
+            return f64::NAN;
+        }
+        if x == 0.0 {
+            return f64::NEG_INFINITY;
+        }
+        if x == f64::INFINITY {
+            return x;
+        }
+
+        // x = 2^k m with m in [sqrt(2) / 2, sqrt(2))
+        let mut k = 0;
+        let mut x = x;
+        if x < f64::MIN_POSITIVE {
+            x *= f64::from_bits(0x4350_0000_0000_0000);
+            k -= 54;
+        }
+        let bits = x.to_bits();
+        let high = ((bits >> 32) as u32) + (0x3ff0_0000 - 0x3fe6_a09e);
+        k += (high >> 20) as i32 - 0x3ff;
+        let high = (high & 0x000f_ffff) + 0x3fe6_a09e;
+        let m = f64::from_bits(((high as u64) << 32) | (bits & 0xffff_ffff));
+
+        let f = m - 1.0;
+        let half_f_squared = 0.5 * f * f;
+        let s = f / (2.0 + f);
+        let z = s * s;
+        let w = z * z;
+        let t1 = w * (LG[1] + w * (LG[3] + w * LG[5]));
+        let t2 = z * (LG[0] + w * (LG[2] + w * (LG[4] + w * LG[6])));
+        let k = k as f64;
+        s * (half_f_squared + t1 + t2) + k * LN2_LO - half_f_squared + f + k * LN2_HI
+    }
+
+    // Only positive bases are needed, negative ones give NaN whatever the exponent
+    pub fn powf(x: f64, y: f64) -> f64 {
+        if y == 0.0 {
+            return 1.0;
+        }
+        exp(y * ln(x))
+    }
+
+    fn atan(x: f64) -> f64 {
+        const ATAN_HI: [f64; 4] = [
+            4.636_476_090_008_061e-1,
+            FRAC_PI_4,
+            9.827_937_232_473_29e-1,
+            FRAC_PI_2,
+        ];
+        const ATAN_LO: [f64; 4] = [
+            2.269_877_745_296_168_7e-17,
+            3.061_616_997_868_383e-17,
+            1.390_331_103_123_099_8e-17,
+            6.123_233_995_736_766e-17,
+        ];
+        const T: [f64; 11] = [
+            3.333_333_333_333_293e-1,
+            -1.999_999_999_987_648_3e-1,
+            1.428_571_427_250_346_6e-1,
+            -1.111_111_040_546_235_6e-1,
+            9.090_887_133_436_507e-2,
+            -7.691_876_205_044_83e-2,
+            6.661_073_137_387_531e-2,
+            -5.833_570_133_790_573_5e-2,
+            4.976_877_994_615_932_4e-2,
+            -3.653_157_274_421_691_6e-2,
+            1.628_582_011_536_578_2e-2,
+        ];
+        if x.is_nan() {
+            return x;
+        }
+
+        let sign = if x.is_sign_negative() { -1.0 } else { 1.0 };
+        let mut ax = x.abs();
+        if ax >= 7.378_697_629_483_821e19 {
+            return sign * (ATAN_HI[3] + ATAN_LO[3]);
+        }
+
+        // Shifted next to one of the tabulated angles
+        let id = if ax < 0.4375 {
+            None
+        } else if ax < 0.6875 {
+            ax = (2.0 * ax - 1.0) / (2.0 + ax);
+            Some(0)
+        } else if ax < 1.1875 {
+            ax = (ax - 1.0) / (ax + 1.0);
+            Some(1)
+        } else if ax < 2.4375 {
+            ax = (ax - 1.5) / (1.0 + 1.5 * ax);
+            Some(2)
+        } else {
+            ax = -1.0 / ax;
+            Some(3)
+        };
+
+        let z = ax * ax;
+        let w = z * z;
+        let s1 = z * (T[0] + w * (T[2] + w * (T[4] + w * (T[6] + w * (T[8] + w * T[10])))));
+        let s2 = w * (T[1] + w * (T[3] + w * (T[5] + w * (T[7] + w * T[9]))));
+        match id {
+            None => x - x * (s1 + s2),
+            Some(id) => sign * (ATAN_HI[id] - ((ax * (s1 + s2) - ATAN_LO[id]) - ax)),
+        }
+    }
+
+    pub fn atan2(y: f64, x: f64) -> f64 {
+        if x.is_nan() || y.is_nan() {
+            return f64::NAN;
+        }
+        if x == 0.0 {
+            return match y {
+                _ if y > 0.0 => FRAC_PI_2,
+                _ if y < 0.0 => -FRAC_PI_2,
+                _ if x.is_sign_negative() => PI.copysign(y),
+                _ => y,
+            };
+        }
+
+        let angle = atan(y / x);
+        if x > 0.0 {
+            angle
+        } else if y.is_sign_negative() {
+            angle - PI
+        } else {
+            angle + PI
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(expected: f64, actual: f64) {
+        let tolerance = 4.0 * f64::EPSILON * expected.abs().max(f64::MIN_POSITIVE);
+        assert!(
+            (expected - actual).abs() <= tolerance,
+            "{expected} != {actual}"
+        );
+    }
+
+    fn samples() -> impl Iterator<Item = f64> {
+        (-2000..=2000).map(|index| index as f64 * 0.0173 + 0.001)
+    }
+
+    #[test]
+    fn test_software_matches_libm() {
+        for x in samples() {
+            let (sin, cos) = soft::sin_cos(x);
+            // Absolute error near the zeros
+            assert!((x.sin() - sin).abs() < 2.0 * f64::EPSILON, "sin {x}");
+            assert!((x.cos() - cos).abs() < 2.0 * f64::EPSILON, "cos {x}");
+            assert_close(x.exp(), soft::exp(x));
+            assert_close(x.atan2(1.5), soft::atan2(x, 1.5));
+            assert_close(x.atan2(-0.5), soft::atan2(x, -0.5));
+            if x > 0.0 {
+                assert_close(x.ln(), soft::ln(x));
+                assert_close(0.95_f64.powf(x), soft::powf(0.95, x));
+            }
+        }
+
+        assert_close(1e-310_f64.ln(), soft::ln(1e-310));
+        assert_close((-740.0_f64).exp(), soft::exp(-740.0));
+        assert_close(700.0_f64.exp(), soft::exp(700.0));
+        assert_eq!(f64::INFINITY, soft::exp(710.0));
+        assert_eq!(0.0, soft::powf(0.0, 0.5));
+        assert_eq!(1.0, soft::powf(0.0, 0.0));
+        assert_eq!(PI, soft::atan2(0.0, -1.0));
+        assert_eq!(-FRAC_PI_2, soft::atan2(-3.0, 0.0));
+        assert!(soft::sin_cos(f64::INFINITY).0.is_nan());
+    }
+}
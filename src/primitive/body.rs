@@ -3,6 +3,7 @@ use crate::Shape;
 use crate::primitive::Mass;
 
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Body {
     shape: Shape,
     mass: Mass,
@@ -11,6 +12,10 @@ pub struct Body {
     pub acceleration: Vec2,
     pub force: Vec2,
     pub friction: f64,
+    // Counter clockwise in radians. Contacts push bodies apart without spinning them
+    pub angle: f64,
+    pub angular_velocity: f64,
+    pub torque: f64,
 }
 
 impl Body {
@@ -23,6 +28,9 @@ impl Body {
             acceleration: Vec2::default(),
             force: Vec2::default(),
             friction: 0.95,
+            angle: 0.0,
+            angular_velocity: 0.0,
+            torque: 0.0,
         }
     }
 
@@ -33,4 +41,20 @@ impl Body {
     pub const fn get_mass(&self) -> &Mass {
         &self.mass
     }
+
+    pub fn get_inverse_inertia(&self) -> f64 {
+        Self::inverse_inertia(&self.shape, &self.mass)
+    }
+
+    pub fn inverse_inertia(shape: &Shape, mass: &Mass) -> f64 {
+        let Mass::Value(mass) = *mass else {
+            return 0.0;
+        };
+
+        let inertia = match *shape {
+            Shape::Circle { radius } => mass * (radius * radius) / 2.0,
+            Shape::Square { edge_length } => mass * (edge_length * edge_length) / 6.0,
+        };
+        1.0 / inertia
+    }
 }
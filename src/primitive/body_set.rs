@@ -0,0 +1,368 @@
+use super::{Body, Mass, Shape, Vec2};
+use crate::math;
+
+// Handle to a body, the generation tells apart bodies which reused the same slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Entity {
+    index: usize,
+    generation: u32,
+}
+
+impl Entity {
+    pub const fn index(&self) -> usize {
+        self.index
+    }
+}
+
+// Structure of arrays storage, every column is indexed by slot
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "BodySetRecord"))]
+pub struct BodySet {
+    shapes: Vec<Shape>,
+    masses: Vec<Mass>,
+    pub positions: Vec<Vec2>,
+    pub velocities: Vec<Vec2>,
+    pub accelerations: Vec<Vec2>,
+    pub forces: Vec<Vec2>,
+    pub frictions: Vec<f64>,
+    pub angles: Vec<f64>,
+    pub angular_velocities: Vec<f64>,
+    pub torques: Vec<f64>,
+    generations: Vec<u32>,
+    occupied: Vec<bool>,
+    free_slots: Vec<usize>,
+}
+
+// The columns as they were read, checked before they become a `BodySet`, which would
+// otherwise only panic once the inconsistent slots get used
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct BodySetRecord {
+    shapes: Vec<Shape>,
+    masses: Vec<Mass>,
+    positions: Vec<Vec2>,
+    velocities: Vec<Vec2>,
+    accelerations: Vec<Vec2>,
+    forces: Vec<Vec2>,
+    frictions: Vec<f64>,
+    angles: Vec<f64>,
+    angular_velocities: Vec<f64>,
+    torques: Vec<f64>,
+    generations: Vec<u32>,
+    occupied: Vec<bool>,
+    free_slots: Vec<usize>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<BodySetRecord> for BodySet {
+    type Error = String;
+
+    fn try_from(record: BodySetRecord) -> Result<Self, Self::Error> {
+        let slot_count = record.occupied.len();
+        let columns = [
+            ("shapes", record.shapes.len()),
+            ("masses", record.masses.len()),
+            ("positions", record.positions.len()),
+            ("velocities", record.velocities.len()),
+            ("accelerations", record.accelerations.len()),
+            ("forces", record.forces.len()),
+            ("frictions", record.frictions.len()),
+            ("angles", record.angles.len()),
+            ("angular_velocities", record.angular_velocities.len()),
+            ("torques", record.torques.len()),
+            ("generations", record.generations.len()),
+        ];
+        if let Some((name, len)) = columns.iter().find(|(_, len)| *len != slot_count) {
+            return Err(format!("{name} has {len} entries for {slot_count} slots"));
+        }
+
+        // Every unoccupied slot is free exactly once
+        let mut listed = vec![false; slot_count];
+        for &index in &record.free_slots {
+            if index >= slot_count {
+                return Err(format!("free slot {index} is out of range"));
+            }
+            if record.occupied[index] {
+                return Err(format!("free slot {index} is occupied"));
+            }
+            if std::mem::replace(&mut listed[index], true) {
+                return Err(format!("free slot {index} is listed twice"));
+            }
+        }
+        let unoccupied = record.occupied.iter().filter(|occupied| !**occupied).count();
+        if unoccupied != record.free_slots.len() {
+            return Err(format!("{unoccupied} slots are unoccupied, but not all of them are free"));
+        }
+
+        Ok(Self {
+            shapes: record.shapes,
+            masses: record.masses,
+            positions: record.positions,
+            velocities: record.velocities,
+            accelerations: record.accelerations,
+            forces: record.forces,
+            frictions: record.frictions,
+            angles: record.angles,
+            angular_velocities: record.angular_velocities,
+            torques: record.torques,
+            generations: record.generations,
+            occupied: record.occupied,
+            free_slots: record.free_slots,
+        })
+    }
+}
+
+impl BodySet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.occupied.len() - self.free_slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Number of slots including the free ones, i.e. the length of every column
+    pub fn slot_count(&self) -> usize {
+        self.occupied.len()
+    }
+
+    pub fn shapes(&self) -> &[Shape] {
+        &self.shapes
+    }
+
+    pub fn masses(&self) -> &[Mass] {
+        &self.masses
+    }
+
+    pub fn insert(&mut self, body: Body) -> Entity {
+        let index = match self.free_slots.pop() {
+            Some(index) => {
+                self.occupied[index] = true;
+                self.shapes[index] = *body.get_shape();
+                self.masses[index] = *body.get_mass();
+                index
+            }
+            None => {
+                self.occupied.push(true);
+                self.generations.push(0);
+                self.shapes.push(*body.get_shape());
+                self.masses.push(*body.get_mass());
+                self.positions.push(Vec2::default());
+                self.velocities.push(Vec2::default());
+                self.accelerations.push(Vec2::default());
+                self.forces.push(Vec2::default());
+                self.frictions.push(0.0);
+                self.angles.push(0.0);
+                self.angular_velocities.push(0.0);
+                self.torques.push(0.0);
+                self.occupied.len() - 1
+            }
+        };
+
+        self.store(index, &body);
+        self.entity(index)
+    }
+
+    pub fn remove(&mut self, entity: Entity) -> Option<Body> {
+        let index = self.slot(entity)?;
+        let body = self.body_at(index);
+
+        self.occupied[index] = false;
+        self.generations[index] = self.generations[index].wrapping_add(1);
+        self.free_slots.push(index);
+
+        // Free slots stay inert when whole columns get integrated
+        self.masses[index] = Mass::Infinity;
+        self.velocities[index] = Vec2::default();
+        self.accelerations[index] = Vec2::default();
+        self.forces[index] = Vec2::default();
+        self.angular_velocities[index] = 0.0;
+        self.torques[index] = 0.0;
+
+        Some(body)
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.slot(entity).is_some()
+    }
+
+    pub fn slot(&self, entity: Entity) -> Option<usize> {
+        let occupied = *self.occupied.get(entity.index)?;
+        if occupied && self.generations[entity.index] == entity.generation {
+            Some(entity.index)
+        } else {
+            None
+        }
+    }
+
+    pub fn entity(&self, index: usize) -> Entity {
+        Entity {
+            index,
+            generation: self.generations[index],
+        }
+    }
+
+    pub fn slots(&self) -> impl Iterator<Item = usize> + '_ {
+        self.occupied
+            .iter()
+            .enumerate()
+            .filter(|(_, occupied)| **occupied)
+            .map(|(index, _)| index)
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.slots().map(|index| self.entity(index))
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<Body> {
+        self.slot(entity).map(|index| self.body_at(index))
+    }
+
+    // Gathers the columns of a slot into a body
+    pub fn body_at(&self, index: usize) -> Body {
+        let mut body = Body::new(
+            self.shapes[index],
+            self.positions[index],
+            self.masses[index],
+        );
+        body.velocity = self.velocities[index];
+        body.acceleration = self.accelerations[index];
+        body.force = self.forces[index];
+        body.friction = self.frictions[index];
+        body.angle = self.angles[index];
+        body.angular_velocity = self.angular_velocities[index];
+        body.torque = self.torques[index];
+        body
+    }
+
+    // Scatters the mutable state of a body back into the columns of a slot
+    pub fn store(&mut self, index: usize, body: &Body) {
+        self.positions[index] = body.position;
+        self.velocities[index] = body.velocity;
+        self.accelerations[index] = body.acceleration;
+        self.forces[index] = body.force;
+        self.frictions[index] = body.friction;
+        self.angles[index] = body.angle;
+        self.angular_velocities[index] = body.angular_velocity;
+        self.torques[index] = body.torque;
+    }
+
+    // Gathers two slots, lets `process` mutate them and scatters them back. Panics when both
+    // slots are the same, as the second body would silently overwrite the first one
+    pub fn with_pair(
+        &mut self,
+        lhs: usize,
+        rhs: usize,
+        process: impl FnOnce(&mut Body, &mut Body),
+    ) {
+        assert_ne!(lhs, rhs, "A pair needs two different slots!");
+        let mut lhs_body = self.body_at(lhs);
+        let mut rhs_body = self.body_at(rhs);
+        process(&mut lhs_body, &mut rhs_body);
+        self.store(lhs, &lhs_body);
+        self.store(rhs, &rhs_body);
+    }
+
+    pub fn integrate(&mut self, delta: f64) {
+        self.integrate_positions(delta);
+        self.integrate_velocities(delta);
+    }
+
+    pub fn integrate_positions(&mut self, delta: f64) {
+        for index in 0..self.slot_count() {
+            self.positions[index] += self.velocities[index] * delta;
+            self.angles[index] += self.angular_velocities[index] * delta;
+        }
+    }
+
+    pub fn integrate_velocities(&mut self, delta: f64) {
+        for index in 0..self.slot_count() {
+            let inverse_mass = self.masses[index].get_inverse();
+            self.accelerations[index] = self.forces[index] * inverse_mass;
+
+            self.velocities[index] += self.accelerations[index] * delta;
+            self.velocities[index] *= math::powf(self.frictions[index], delta);
+
+            let inverse_inertia = Body::inverse_inertia(&self.shapes[index], &self.masses[index]);
+            self.angular_velocities[index] += self.torques[index] * inverse_inertia * delta;
+            self.angular_velocities[index] *= math::powf(self.frictions[index], delta);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stale_handles() {
+        let mut bodies = BodySet::new();
+        let circle = Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(1.0, 2.0),
+            Mass::Value(1.0),
+        );
+        let square = Body::new(
+            Shape::Square { edge_length: 1.0 },
+            Vec2::new(),
+            Mass::Infinity,
+        );
+
+        let first = bodies.insert(circle);
+        let second = bodies.insert(square);
+        assert_eq!(2, bodies.len());
+        assert_eq!(Vec2::new_at(1.0, 2.0), bodies.positions[first.index()]);
+
+        assert!(bodies.remove(first).is_some());
+        assert!(bodies.remove(first).is_none());
+
+        // The slot gets reused, but the old handle stays invalid
+        let third = bodies.insert(square);
+        assert_eq!(first.index(), third.index());
+        assert!(!bodies.contains(first));
+        assert!(bodies.get(first).is_none());
+        assert!(bodies.get(third).is_some());
+        assert_eq!(vec![third, second], bodies.entities().collect::<Vec<_>>());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_malformed_record() {
+        let mut bodies = BodySet::new();
+        let circle = Body::new(Shape::Circle { radius: 1.0 }, Vec2::new(), Mass::Value(1.0));
+        let removed = bodies.insert(circle);
+        bodies.insert(circle);
+        bodies.remove(removed);
+
+        let record = serde_json::to_value(&bodies).unwrap();
+        let loaded: BodySet = serde_json::from_value(record.clone()).unwrap();
+        assert_eq!(1, loaded.len());
+
+        let malformed = |field: &str, value: serde_json::Value| {
+            let mut record = record.clone();
+            record[field] = value;
+            serde_json::from_value::<BodySet>(record).err().unwrap().to_string()
+        };
+        assert!(malformed("positions", serde_json::json!([])).contains("positions has 0"));
+        assert!(malformed("generations", serde_json::json!([1])).contains("generations has 1"));
+        assert!(malformed("free_slots", serde_json::json!([5])).contains("out of range"));
+        assert!(malformed("free_slots", serde_json::json!([1])).contains("is occupied"));
+        assert!(malformed("free_slots", serde_json::json!([0, 0])).contains("listed twice"));
+        assert!(malformed("free_slots", serde_json::json!([])).contains("not all of them"));
+    }
+
+    #[test]
+    #[should_panic(expected = "two different slots")]
+    fn test_pair_of_one_slot() {
+        let mut bodies = BodySet::new();
+        let circle = Body::new(Shape::Circle { radius: 1.0 }, Vec2::new(), Mass::Value(1.0));
+        let index = bodies.insert(circle).index();
+        bodies.with_pair(index, index, |_, _| {});
+    }
+}
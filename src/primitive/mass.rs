@@ -1,5 +1,6 @@
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Mass {
     Value(f64),
     Infinity,
@@ -4,10 +4,12 @@ pub mod vec2;
 pub mod shape;
 pub mod body;
 pub mod mass;
+pub mod body_set;
 
 pub use stack_vec::StackVec;
 pub use hybrid_vec::HybridVec;
 pub use vec2::Vec2;
 pub use shape::Shape;
 pub use body::Body;
-pub use mass::Mass;
\ No newline at end of file
+pub use mass::Mass;
+pub use body_set::BodySet;
\ No newline at end of file
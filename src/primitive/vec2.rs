@@ -1,6 +1,9 @@
 use std::ops::{Sub, SubAssign, AddAssign, Add, Mul, MulAssign, Neg};
 
+use crate::math;
+
 #[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Vec2 {
     pub x: f64,
     pub y: f64,
@@ -17,13 +20,13 @@ impl Vec2 {
 
     pub fn new_polar(angle: f64, len: f64) -> Self {
         Self {
-            x: angle.cos() * len,
-            y: angle.sin() * len,
+            x: math::cos(angle) * len,
+            y: math::sin(angle) * len,
         }
     }
 
     pub fn len_squared(self) -> f64 {
-        self.x.mul_add(self.x, self.y.powi(2))
+        math::mul_add(self.x, self.x, self.y * self.y)
     }
 
     pub fn len(self) -> f64 {
@@ -60,11 +63,32 @@ impl Vec2 {
     }
 
     pub fn scalar_product(&self, rhs: &Self) -> f64 {
-        self.x.mul_add(rhs.x, self.y * rhs.y)
+        math::mul_add(self.x, rhs.x, self.y * rhs.y)
     }
 
     pub fn angle_rad(&self) -> f64 {
-        self.y.atan2(self.x)
+        math::atan2(self.y, self.x)
+    }
+
+    // z component of the 3d cross product
+    pub fn cross(&self, rhs: &Self) -> f64 {
+        math::mul_add(self.x, rhs.y, -self.y * rhs.x)
+    }
+
+    // Rotated counter clockwise by 90 degrees
+    pub const fn perpendicular(&self) -> Self {
+        Self {
+            x: -self.y,
+            y: self.x,
+        }
+    }
+
+    pub fn rotated(&self, angle: f64) -> Self {
+        let (sin, cos) = math::sin_cos(angle);
+        Self {
+            x: self.x * cos - self.y * sin,
+            y: self.x * sin + self.y * cos,
+        }
     }
 
 }
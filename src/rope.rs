@@ -0,0 +1,398 @@
+use std::fmt;
+
+use crate::{
+    constraint::BungeeSpring,
+    primitive::{Body, Mass, Shape, Vec2},
+    world::{ConstraintHandle, Entity, World},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anchor {
+    Body(Entity),
+    // Pinned by a static body which belongs to the rope
+    Point(Vec2),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RopeEnd {
+    Start,
+    End,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RopeError {
+    // The body of an `Anchor::Body` is not in the world, e.g. because it got removed
+    MissingAnchor(Entity),
+    // Both ends sit at the same point, so there is no line to lay the segments out on
+    CoincidentAnchors,
+    // An end would hang off a segment of the rope itself
+    OwnSegment(Entity),
+    // The builder asks for zero segments
+    NoSegments,
+}
+
+impl fmt::Display for RopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingAnchor(entity) => {
+                write!(f, "the anchor body {entity:?} is not in the world")
+            }
+            Self::CoincidentAnchors => write!(f, "both anchors are at the same point"),
+            Self::OwnSegment(entity) => {
+                write!(f, "the anchor body {entity:?} is a segment of the rope")
+            }
+            Self::NoSegments => write!(f, "a rope needs at least one segment"),
+        }
+    }
+}
+
+impl std::error::Error for RopeError {}
+
+#[derive(Debug, Clone, Copy)]
+struct Attachment {
+    anchor: Entity,
+    link: ConstraintHandle,
+    // Static bodies created for `Anchor::Point` get removed along with the attachment
+    owned: bool,
+}
+
+pub struct RopeBuilder {
+    pub segments: usize,
+    pub segment_length: f64,
+    pub segment_radius: f64,
+    pub segment_mass: f64,
+    pub spring_constant: f64,
+}
+
+impl Default for RopeBuilder {
+    fn default() -> Self {
+        Self {
+            segments: 10,
+            segment_length: 5.0,
+            segment_radius: 2.0,
+            segment_mass: 1.0,
+            spring_constant: 100.0,
+        }
+    }
+}
+
+impl RopeBuilder {
+    // Lays the segments out on the straight line between both anchors, every link keeps its
+    // rest length at `segment_length` no matter how far apart the anchors are
+    pub fn build(
+        &self,
+        world: &mut World,
+        start: Anchor,
+        end: Anchor,
+    ) -> Result<Rope, RopeError> {
+        if self.segments == 0 {
+            return Err(RopeError::NoSegments);
+        }
+
+        // Nothing gets added to the world unless both anchors are there and apart
+        let from = Self::anchor_position(world, start)?;
+        let to = Self::anchor_position(world, end)?;
+        if from == to {
+            return Err(RopeError::CoincidentAnchors);
+        }
+        let step = (to - from) * (1.0 / (self.segments + 1) as f64);
+
+        let segments: Vec<Entity> = (1..=self.segments)
+            .map(|index| {
+                world.add(Body::new(
+                    Shape::Circle {
+                        radius: self.segment_radius,
+                    },
+                    from + step * index as f64,
+                    Mass::Value(self.segment_mass),
+                ))
+            })
+            .collect();
+
+        let mut rope = Rope {
+            segments,
+            links: vec![],
+            segment_length: self.segment_length,
+            spring_constant: self.spring_constant,
+            ends: [None, None],
+        };
+
+        for index in 1..rope.segments.len() {
+            let (lhs, rhs) = (rope.segments[index - 1], rope.segments[index]);
+            let link = rope.link(world, lhs, rhs);
+            rope.links.push(link);
+        }
+
+        rope.attach(world, RopeEnd::Start, start)?;
+        rope.attach(world, RopeEnd::End, end)?;
+        Ok(rope)
+    }
+
+    fn anchor_position(world: &World, anchor: Anchor) -> Result<Vec2, RopeError> {
+        match anchor {
+            Anchor::Body(entity) => world
+                .body(entity)
+                .map(|body| body.position)
+                .ok_or(RopeError::MissingAnchor(entity)),
+            Anchor::Point(point) => Ok(point),
+        }
+    }
+}
+
+// Segments linked by bungee springs, which can hang off a body or a point at either end
+pub struct Rope {
+    pub segments: Vec<Entity>,
+    // Links between neighbouring segments, the ends are not included
+    pub links: Vec<ConstraintHandle>,
+    segment_length: f64,
+    spring_constant: f64,
+    ends: [Option<Attachment>; 2],
+}
+
+impl Rope {
+    pub fn segment(&self, end: RopeEnd) -> Entity {
+        match end {
+            RopeEnd::Start => self.segments[0],
+            RopeEnd::End => self.segments[self.segments.len() - 1],
+        }
+    }
+
+    pub fn anchor(&self, end: RopeEnd) -> Option<Entity> {
+        self.ends[end as usize].map(|attachment| attachment.anchor)
+    }
+
+    pub fn end_link(&self, end: RopeEnd) -> Option<ConstraintHandle> {
+        self.ends[end as usize].map(|attachment| attachment.link)
+    }
+
+    // Replaces whatever the end was attached to before, which stays in place when the
+    // new anchor is rejected
+    pub fn attach(
+        &mut self,
+        world: &mut World,
+        end: RopeEnd,
+        anchor: Anchor,
+    ) -> Result<(), RopeError> {
+        RopeBuilder::anchor_position(world, anchor)?;
+        if let Anchor::Body(entity) = anchor {
+            if self.segments.contains(&entity) {
+                return Err(RopeError::OwnSegment(entity));
+            }
+        }
+        self.detach(world, end);
+
+        let (anchor, owned) = match anchor {
+            Anchor::Body(entity) => (entity, false),
+            Anchor::Point(point) => {
+                let radius = match world.body(self.segment(end)).map(|body| *body.get_shape()) {
+                    Some(Shape::Circle { radius }) => radius,
+                    _ => 1.0,
+                };
+                let body = Body::new(Shape::Circle { radius }, point, Mass::Infinity);
+                (world.add(body), true)
+            }
+        };
+
+        let link = self.link(world, anchor, self.segment(end));
+        self.ends[end as usize] = Some(Attachment {
+            anchor,
+            link,
+            owned,
+        });
+        Ok(())
+    }
+
+    pub fn detach(&mut self, world: &mut World, end: RopeEnd) {
+        let Some(attachment) = self.ends[end as usize].take() else {
+            return;
+        };
+
+        world.remove_constraint(attachment.link);
+        world.enable_collision(attachment.anchor, self.segment(end));
+        if attachment.owned {
+            world.remove(attachment.anchor);
+        }
+    }
+
+    // Anchors which were passed as bodies stay in the world
+    pub fn remove(mut self, world: &mut World) {
+        self.detach(world, RopeEnd::Start);
+        self.detach(world, RopeEnd::End);
+        for link in self.links {
+            world.remove_constraint(link);
+        }
+        for segment in self.segments {
+            world.remove(segment);
+        }
+    }
+
+    fn link(&self, world: &mut World, lhs: Entity, rhs: Entity) -> ConstraintHandle {
+        let mut spring = BungeeSpring::new(lhs, rhs, self.segment_length);
+        spring.spring_constant = self.spring_constant;
+        world.disable_collision(lhs, rhs);
+        world.add_constraint(Box::new(spring))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{SolverMode, WorldEvent};
+
+    const DELTA: std::time::Duration = std::time::Duration::from_millis(16);
+
+    fn step(world: &mut World, rope: &Rope) -> Vec<WorldEvent> {
+        for segment in &rope.segments {
+            world.apply_force(*segment, Vec2::new_at(0.0, -10.0));
+        }
+        world.update(DELTA)
+    }
+
+    #[test]
+    fn test_rope_between_points() {
+        let mut world = World::new();
+        // Thick segments overlap their neighbours, which must not count as contacts
+        let builder = RopeBuilder {
+            segments: 8,
+            segment_radius: 3.0,
+            ..RopeBuilder::default()
+        };
+        let start = Vec2::new_at(-20.0, 0.0);
+        let end = Vec2::new_at(20.0, 0.0);
+        let rope = builder
+            .build(&mut world, Anchor::Point(start), Anchor::Point(end))
+            .unwrap();
+        assert_eq!(8 + 2, world.ids().count());
+        assert_eq!(7, rope.links.len());
+        assert_eq!(7 + 2, world.constraints().count());
+
+        let mut chain = vec![rope.anchor(RopeEnd::Start).unwrap()];
+        chain.extend(&rope.segments);
+        chain.push(rope.anchor(RopeEnd::End).unwrap());
+        let adjacent = |lhs: Entity, rhs: Entity| {
+            let position = |entity| chain.iter().position(|other| *other == entity).unwrap();
+            position(lhs).abs_diff(position(rhs)) == 1
+        };
+        for _ in 0..120 {
+            for event in step(&mut world, &rope) {
+                if let WorldEvent::Collision(data) = event {
+                    assert!(!adjacent(data.ids[0], data.ids[1]));
+                }
+            }
+        }
+
+        // Sagging, but held up at both ends
+        let lowest = rope
+            .segments
+            .iter()
+            .map(|segment| world.body(*segment).unwrap().position.y)
+            .fold(f64::INFINITY, f64::min);
+        assert!(lowest < -5.0);
+        assert!(lowest > -40.0, "{lowest}");
+        let anchor = rope.anchor(RopeEnd::Start).unwrap();
+        assert_eq!(start, world.body(anchor).unwrap().position);
+
+        rope.remove(&mut world);
+        assert_eq!(0, world.ids().count());
+        assert_eq!(0, world.constraints().count());
+    }
+
+    #[test]
+    fn test_attach_and_detach() {
+        let mut world = World::new();
+        world.set_solver_mode(SolverMode::Xpbd { substeps: 8 });
+        let hook = world.add(Body::new(
+            Shape::Square { edge_length: 2.0 },
+            Vec2::new_at(0.0, 50.0),
+            Mass::Infinity,
+        ));
+        let mut rope = RopeBuilder::default()
+            .build(
+                &mut world,
+                Anchor::Body(hook),
+                Anchor::Point(Vec2::new_at(50.0, 50.0)),
+            )
+            .unwrap();
+        assert!(world.is_collision_disabled(hook, rope.segment(RopeEnd::Start)));
+
+        // The free end swings down below the hook
+        rope.detach(&mut world, RopeEnd::End);
+        assert!(rope.anchor(RopeEnd::End).is_none());
+        assert_eq!(1 + 10, world.ids().count());
+        for _ in 0..300 {
+            step(&mut world, &rope);
+        }
+        let free_end = world.body(rope.segment(RopeEnd::End)).unwrap().position;
+        assert!(free_end.y < 10.0, "{free_end:?}");
+        assert!(free_end.x.abs() < 20.0);
+
+        // Pulled back up once it hangs off a second point
+        rope.attach(
+            &mut world,
+            RopeEnd::End,
+            Anchor::Point(Vec2::new_at(10.0, 50.0)),
+        )
+        .unwrap();
+        for _ in 0..300 {
+            step(&mut world, &rope);
+        }
+        let free_end = world.body(rope.segment(RopeEnd::End)).unwrap().position;
+        assert!(free_end.y > 40.0);
+
+        rope.remove(&mut world);
+        assert_eq!(vec![hook], world.ids().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_missing_anchor() {
+        let mut world = World::new();
+        let hook = world.add(Body::new(
+            Shape::Square { edge_length: 2.0 },
+            Vec2::new_at(0.0, 50.0),
+            Mass::Infinity,
+        ));
+        world.remove(hook);
+
+        let builder = RopeBuilder::default();
+        let start = Anchor::Point(Vec2::new());
+        let result = builder.build(&mut world, start, Anchor::Body(hook));
+        assert_eq!(Some(RopeError::MissingAnchor(hook)), result.err());
+        assert_eq!(0, world.ids().count());
+
+        // A stale anchor leaves the current attachment alone
+        let end = Anchor::Point(Vec2::new_at(50.0, 0.0));
+        let mut rope = builder.build(&mut world, start, end).unwrap();
+        let anchor = rope.anchor(RopeEnd::End);
+        let result = rope.attach(&mut world, RopeEnd::End, Anchor::Body(hook));
+        assert_eq!(Err(RopeError::MissingAnchor(hook)), result);
+        assert_eq!(anchor, rope.anchor(RopeEnd::End));
+        assert!(world.constraint(rope.end_link(RopeEnd::End).unwrap()).is_some());
+    }
+
+    #[test]
+    fn test_rejected_anchors() {
+        let mut world = World::new();
+        let builder = RopeBuilder::default();
+        let point = Anchor::Point(Vec2::new_at(10.0, 0.0));
+        let result = builder.build(&mut world, point, point);
+        assert_eq!(Some(RopeError::CoincidentAnchors), result.err());
+        assert_eq!(0, world.ids().count());
+
+        let empty = RopeBuilder {
+            segments: 0,
+            ..RopeBuilder::default()
+        };
+        let result = empty.build(&mut world, Anchor::Point(Vec2::new()), point);
+        assert_eq!(Some(RopeError::NoSegments), result.err());
+        assert_eq!(0, world.ids().count());
+
+        // Hanging an end off the rope itself keeps the current attachment
+        let mut rope = builder.build(&mut world, Anchor::Point(Vec2::new()), point).unwrap();
+        let anchor = rope.anchor(RopeEnd::Start);
+        let segment = rope.segments[3];
+        let result = rope.attach(&mut world, RopeEnd::Start, Anchor::Body(segment));
+        assert_eq!(Err(RopeError::OwnSegment(segment)), result);
+        assert_eq!(anchor, rope.anchor(RopeEnd::Start));
+        step(&mut world, &rope);
+    }
+}
@@ -0,0 +1,525 @@
+use std::{collections::HashMap, f64::consts::PI, fmt};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    constraint::{
+        Constraint, DampedSpring, DistanceJoint, PrismaticJoint, RevoluteJoint, RopeJoint,
+        Softness, WeldJoint,
+    },
+    force_generator::Gravity,
+    primitive::{Body, Mass, Shape, Vec2},
+    world::{Entity, SolverMode, World, DEFAULT_SOLVER_ITERATIONS},
+};
+
+// Declarative description of a world, bodies and constraints refer to each other by name
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Scene {
+    pub gravity: Vec2,
+    pub solver: SolverSettings,
+    // Generators draw different bodies on every build unless seeded
+    pub seed: Option<u64>,
+    pub materials: Vec<Material>,
+    pub bodies: Vec<BodyDescription>,
+    pub constraints: Vec<ConstraintDescription>,
+    pub generators: Vec<Generator>,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SolverSettings {
+    pub iterations: usize,
+    pub mode: SolverMode,
+}
+
+impl Default for SolverSettings {
+    fn default() -> Self {
+        Self {
+            iterations: DEFAULT_SOLVER_ITERATIONS,
+            mode: SolverMode::Impulse,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Material {
+    pub name: String,
+    // Mass per unit of area
+    pub density: f64,
+    pub friction: f64,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BodyDescription {
+    pub name: Option<String>,
+    pub shape: Shape,
+    pub position: Vec2,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub velocity: Vec2,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub angle: f64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub angular_velocity: f64,
+    // Bodies without one get a density of 1 and the default friction
+    pub material: Option<String>,
+    // Static bodies get an infinite mass whatever their material
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub fixed: bool,
+}
+
+// Lengths which are left out keep the distance the bodies start at
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConstraintDescription {
+    Distance {
+        bodies: (String, String),
+        length: Option<f64>,
+        #[cfg_attr(feature = "serde", serde(default))]
+        softness: Softness,
+    },
+    Rope {
+        bodies: (String, String),
+        max_length: Option<f64>,
+        #[cfg_attr(feature = "serde", serde(default))]
+        softness: Softness,
+    },
+    Spring {
+        bodies: (String, String),
+        rest_length: Option<f64>,
+        stiffness: f64,
+        damping: f64,
+    },
+    Weld {
+        bodies: (String, String),
+        #[cfg_attr(feature = "serde", serde(default))]
+        softness: Softness,
+    },
+    // Pinned together at a point given in world space
+    Revolute {
+        bodies: (String, String),
+        anchor: Vec2,
+    },
+    // Sliding along an axis given in world space
+    Prismatic {
+        bodies: (String, String),
+        axis: Vec2,
+    },
+}
+
+// Scatters `count` bodies uniformly over the ranges, empty ranges always yield their start
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Generator {
+    pub count: usize,
+    pub shape: ShapeDistribution,
+    pub x: (f64, f64),
+    pub y: (f64, f64),
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub velocity_x: (f64, f64),
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub velocity_y: (f64, f64),
+    pub material: Option<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub fixed: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ShapeDistribution {
+    Circle { radius: (f64, f64) },
+    Square { edge_length: (f64, f64) },
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    UnknownBody(String),
+    UnknownMaterial(String),
+    DuplicateBody(String),
+    // A constraint naming the same body at both ends
+    SelfConstraint(String),
+    #[cfg(feature = "scene")]
+    Io(std::io::Error),
+    #[cfg(feature = "scene")]
+    Parse(ron::error::SpannedError),
+    #[cfg(feature = "scene")]
+    Serialize(ron::Error),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownBody(name) => write!(f, "no body is named {name:?}"),
+            Self::UnknownMaterial(name) => write!(f, "no material is named {name:?}"),
+            Self::DuplicateBody(name) => write!(f, "more than one body is named {name:?}"),
+            Self::SelfConstraint(name) => write!(f, "{name:?} is constrained to itself"),
+            #[cfg(feature = "scene")]
+            Self::Io(error) => write!(f, "{error}"),
+            #[cfg(feature = "scene")]
+            Self::Parse(error) => write!(f, "{error}"),
+            #[cfg(feature = "scene")]
+            Self::Serialize(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl Material {
+    const DEFAULT_DENSITY: f64 = 1.0;
+
+    fn mass(density: f64, shape: &Shape, fixed: bool) -> Mass {
+        if fixed {
+            return Mass::Infinity;
+        }
+
+        let area = match *shape {
+            Shape::Circle { radius } => PI * (radius * radius),
+            Shape::Square { edge_length } => edge_length * edge_length,
+        };
+        Mass::Value(density * area)
+    }
+}
+
+impl Scene {
+    // What the demo used to hard-code: static squares below a rain of circles
+    pub fn demo() -> Self {
+        Self {
+            gravity: Vec2::new_at(0.0, -300.0),
+            generators: vec![
+                Generator {
+                    count: 40,
+                    shape: ShapeDistribution::Square {
+                        edge_length: (20.0, 45.0),
+                    },
+                    x: (-700.0, 700.0),
+                    y: (-400.0, -200.0),
+                    velocity_x: (0.0, 0.0),
+                    velocity_y: (0.0, 0.0),
+                    material: None,
+                    fixed: true,
+                },
+                Generator {
+                    count: 2000,
+                    shape: ShapeDistribution::Circle {
+                        radius: (5.0, 12.0),
+                    },
+                    x: (-1000.0, 1000.0),
+                    y: (500.0, 4500.0),
+                    velocity_x: (-50.0, 50.0),
+                    velocity_y: (-50.0, 50.0),
+                    material: None,
+                    fixed: false,
+                },
+            ],
+            ..Self::default()
+        }
+    }
+
+    pub fn build(&self) -> Result<World, SceneError> {
+        let mut world = World::new();
+        world.set_solver_iterations(self.solver.iterations);
+        world.set_solver_mode(self.solver.mode);
+        if self.gravity.len_squared() > 0.0 {
+            world.add_force_generator(Box::new(Gravity {
+                acceleration: self.gravity,
+            }));
+        }
+
+        let mut named = HashMap::new();
+        for description in &self.bodies {
+            let (density, friction) = self.material(description.material.as_deref())?;
+            let mass = Material::mass(density, &description.shape, description.fixed);
+            let mut body = Body::new(description.shape, description.position, mass);
+            body.velocity = description.velocity;
+            body.angle = description.angle;
+            body.angular_velocity = description.angular_velocity;
+            if let Some(friction) = friction {
+                body.friction = friction;
+            }
+
+            let entity = world.add(body);
+            if let Some(name) = &description.name {
+                if named.insert(name.as_str(), entity).is_some() {
+                    return Err(SceneError::DuplicateBody(name.clone()));
+                }
+            }
+        }
+
+        for description in &self.constraints {
+            let constraint = Self::constraint(&world, &named, description)?;
+            world.add_constraint(constraint);
+        }
+
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        for generator in &self.generators {
+            let (density, friction) = self.material(generator.material.as_deref())?;
+            for _ in 0..generator.count {
+                let body = generator.generate(&mut rng, density, friction);
+                world.add(body);
+            }
+        }
+
+        Ok(world)
+    }
+
+    // Density and friction, the friction stays at the body default without a material
+    fn material(&self, name: Option<&str>) -> Result<(f64, Option<f64>), SceneError> {
+        let Some(name) = name else {
+            return Ok((Material::DEFAULT_DENSITY, None));
+        };
+
+        self.materials
+            .iter()
+            .find(|material| material.name == name)
+            .map(|material| (material.density, Some(material.friction)))
+            .ok_or_else(|| SceneError::UnknownMaterial(name.to_owned()))
+    }
+
+    fn constraint(
+        world: &World,
+        named: &HashMap<&str, Entity>,
+        description: &ConstraintDescription,
+    ) -> Result<Box<dyn Constraint>, SceneError> {
+        let bodies = match description {
+            ConstraintDescription::Distance { bodies, .. }
+            | ConstraintDescription::Rope { bodies, .. }
+            | ConstraintDescription::Spring { bodies, .. }
+            | ConstraintDescription::Weld { bodies, .. }
+            | ConstraintDescription::Revolute { bodies, .. }
+            | ConstraintDescription::Prismatic { bodies, .. } => bodies,
+        };
+        let entity = |name: &String| {
+            named
+                .get(name.as_str())
+                .copied()
+                .ok_or_else(|| SceneError::UnknownBody(name.clone()))
+        };
+        let (entity1, entity2) = (entity(&bodies.0)?, entity(&bodies.1)?);
+        if entity1 == entity2 {
+            return Err(SceneError::SelfConstraint(bodies.0.clone()));
+        }
+        // Both were just added from the descriptions
+        let lhs = world.body(entity1).unwrap();
+        let rhs = world.body(entity2).unwrap();
+        let distance = (rhs.position - lhs.position).len();
+        let local = |body: &Body, point: Vec2| (point - body.position).rotated(-body.angle);
+
+        Ok(match *description {
+            ConstraintDescription::Distance {
+                length, softness, ..
+            } => {
+                let mut joint = DistanceJoint::new(entity1, entity2, length.unwrap_or(distance));
+                joint.softness = softness;
+                Box::new(joint)
+            }
+            ConstraintDescription::Rope {
+                max_length,
+                softness,
+                ..
+            } => {
+                let mut joint = RopeJoint::new(entity1, entity2, max_length.unwrap_or(distance));
+                joint.softness = softness;
+                Box::new(joint)
+            }
+            ConstraintDescription::Spring {
+                rest_length,
+                stiffness,
+                damping,
+                ..
+            } => Box::new(DampedSpring::new(
+                entity1,
+                entity2,
+                rest_length.unwrap_or(distance),
+                stiffness,
+                damping,
+            )),
+            ConstraintDescription::Weld { softness, .. } => {
+                let mut joint = WeldJoint::new(entity1, entity2, local(&lhs, rhs.position));
+                joint.reference_angle = rhs.angle - lhs.angle;
+                joint.softness = softness;
+                Box::new(joint)
+            }
+            ConstraintDescription::Revolute { anchor, .. } => {
+                let mut joint =
+                    RevoluteJoint::new(entity1, entity2, local(&lhs, anchor), local(&rhs, anchor));
+                joint.reference_angle = rhs.angle - lhs.angle;
+                Box::new(joint)
+            }
+            ConstraintDescription::Prismatic { axis, .. } => {
+                let mut joint = PrismaticJoint::new(entity1, entity2, axis.rotated(-lhs.angle));
+                joint.local_anchor2 = local(&rhs, lhs.position);
+                joint.reference_angle = rhs.angle - lhs.angle;
+                Box::new(joint)
+            }
+        })
+    }
+}
+
+#[cfg(feature = "scene")]
+impl Scene {
+    // Options which are left out need no `Some(...)` around them
+    pub fn from_ron(source: &str) -> Result<Self, SceneError> {
+        ron::Options::default()
+            .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+            .from_str(source)
+            .map_err(SceneError::Parse)
+    }
+
+    // Saving works on scene descriptions only, there is no way back from a built `World`,
+    // whose joints and force generators may not fit the format. A running world round-trips
+    // through its own serde implementation instead
+    pub fn to_ron(&self) -> Result<String, SceneError> {
+        let config = ron::ser::PrettyConfig::new()
+            .extensions(ron::extensions::Extensions::IMPLICIT_SOME);
+        ron::ser::to_string_pretty(self, config).map_err(SceneError::Serialize)
+    }
+
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, SceneError> {
+        let source = std::fs::read_to_string(path).map_err(SceneError::Io)?;
+        Self::from_ron(&source)
+    }
+
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), SceneError> {
+        std::fs::write(path, self.to_ron()?).map_err(SceneError::Io)
+    }
+}
+
+impl Generator {
+    fn generate(&self, rng: &mut impl Rng, density: f64, friction: Option<f64>) -> Body {
+        let mut sample = |(start, end): (f64, f64)| {
+            if start < end {
+                rng.gen_range(start..end)
+            } else {
+                start
+            }
+        };
+
+        let position = Vec2::new_at(sample(self.x), sample(self.y));
+        let velocity = Vec2::new_at(sample(self.velocity_x), sample(self.velocity_y));
+        let shape = match self.shape {
+            ShapeDistribution::Circle { radius } => Shape::Circle {
+                radius: sample(radius),
+            },
+            ShapeDistribution::Square { edge_length } => Shape::Square {
+                edge_length: sample(edge_length),
+            },
+        };
+
+        let mut body = Body::new(shape, position, Material::mass(density, &shape, self.fixed));
+        body.velocity = velocity;
+        if let Some(friction) = friction {
+            body.friction = friction;
+        }
+        body
+    }
+}
+
+#[cfg(all(test, feature = "scene"))]
+mod tests {
+    use super::*;
+
+    const PENDULUM: &str = r#"
+        (
+            gravity: (x: 0.0, y: -10.0),
+            solver: (iterations: 12, mode: Xpbd(substeps: 4)),
+            seed: 7,
+            materials: [(name: "rubber", density: 2.0, friction: 0.5)],
+            bodies: [
+                (name: "pivot", shape: Square(edge_length: 2.0), position: (x: 0.0, y: 0.0),
+                    fixed: true),
+                (name: "bob", shape: Circle(radius: 1.0), position: (x: 10.0, y: 0.0),
+                    material: "rubber"),
+            ],
+            constraints: [Distance(bodies: ("pivot", "bob"))],
+            generators: [
+                (count: 5, shape: Circle(radius: (1.0, 2.0)), x: (-50.0, 50.0),
+                    y: (20.0, 20.0)),
+            ],
+        )
+    "#;
+
+    fn positions(world: &World) -> Vec<(u64, u64)> {
+        world
+            .bodies()
+            .map(|body| (body.position.x.to_bits(), body.position.y.to_bits()))
+            .collect()
+    }
+
+    #[test]
+    fn test_build_pendulum() {
+        let scene = Scene::from_ron(PENDULUM).unwrap();
+        let mut world = scene.build().unwrap();
+        assert_eq!(12, world.solver_iterations());
+        assert_eq!(SolverMode::Xpbd { substeps: 4 }, world.solver_mode());
+        assert_eq!(2 + 5, world.ids().count());
+        assert_eq!(1, world.constraints().count());
+
+        let bob = world.entities().nth(1).unwrap().1;
+        assert_eq!(Mass::Value(2.0 * PI), *bob.get_mass());
+        assert_eq!(0.5, bob.friction);
+        for (_, body) in world.entities().skip(2) {
+            assert_eq!(20.0, body.position.y);
+        }
+
+        // Swings down on its string
+        let bob = world.ids().nth(1).unwrap();
+        for _ in 0..30 {
+            world.update(std::time::Duration::from_millis(16));
+        }
+        let position = world.body(bob).unwrap().position;
+        assert!(position.y < -1.0, "{position:?}");
+        assert!((position.len() - 10.0).abs() < 0.1, "{position:?}");
+    }
+
+    #[test]
+    fn test_ron_round_trip() {
+        let scene = Scene::from_ron(PENDULUM).unwrap();
+        let saved = scene.to_ron().unwrap();
+        let loaded = Scene::from_ron(&saved).unwrap();
+        assert_eq!(saved, loaded.to_ron().unwrap());
+
+        // Seeded generators scatter the bodies the same way every time
+        let world = scene.build().unwrap();
+        assert_eq!(positions(&world), positions(&loaded.build().unwrap()));
+
+        // The example file stays in sync with the built-in demo
+        let demo = Scene::from_ron(include_str!("../scenes/demo.ron")).unwrap();
+        assert_eq!(Scene::demo().to_ron().unwrap(), demo.to_ron().unwrap());
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut scene = Scene::from_ron(PENDULUM).unwrap();
+        scene.constraints.push(ConstraintDescription::Weld {
+            bodies: ("pivot".to_owned(), "nobody".to_owned()),
+            softness: Softness::rigid(),
+        });
+        assert!(matches!(scene.build(), Err(SceneError::UnknownBody(name)) if name == "nobody"));
+
+        scene.constraints.pop();
+        scene.constraints.push(ConstraintDescription::Distance {
+            bodies: ("bob".to_owned(), "bob".to_owned()),
+            length: None,
+            softness: Softness::rigid(),
+        });
+        assert!(matches!(scene.build(), Err(SceneError::SelfConstraint(name)) if name == "bob"));
+
+        scene.constraints.pop();
+        scene.bodies[1].material = Some("steel".to_owned());
+        assert!(matches!(scene.build(), Err(SceneError::UnknownMaterial(_))));
+
+        scene.bodies[1].name = Some("pivot".to_owned());
+        scene.bodies[1].material = None;
+        assert!(matches!(scene.build(), Err(SceneError::DuplicateBody(_))));
+
+        assert!(matches!(Scene::from_ron("(gravity: 1)"), Err(SceneError::Parse(_))));
+    }
+}
@@ -0,0 +1,461 @@
+use std::{f64::consts::PI, fmt};
+
+use crate::{
+    bsp::aabb::Aabb,
+    constraint::{AreaConstraint, DistanceJoint, Softness},
+    force_generator::ForceGenerator,
+    primitive::{Body, Mass, Shape, Vec2},
+    world::{ConstraintHandle, Entity, ForceGeneratorHandle, World},
+};
+
+// Particles and constraints making up one soft body, so it can be handled as a unit.
+// Self collision needs nothing extra, the particles are ordinary bodies in the broadphase
+#[derive(Debug, Clone, Default)]
+pub struct SoftBody {
+    pub entities: Vec<Entity>,
+    pub constraints: Vec<ConstraintHandle>,
+    // Volume preservation of blobs in `SolverMode::Xpbd`
+    pub area_constraint: Option<ConstraintHandle>,
+    // Keeps blobs inflated in either solver mode
+    pub pressure: Option<ForceGeneratorHandle>,
+}
+
+impl SoftBody {
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.entities.contains(&entity)
+    }
+
+    pub fn translate(&self, world: &mut World, offset: Vec2) {
+        for entity in &self.entities {
+            world.with_body_mut(*entity, |body| body.position += offset);
+        }
+    }
+
+    pub fn set_velocity(&self, world: &mut World, velocity: Vec2) {
+        for entity in &self.entities {
+            world.with_body_mut(*entity, |body| body.velocity = velocity);
+        }
+    }
+
+    pub fn aabb(&self, world: &World) -> Option<Aabb> {
+        Aabb::merged(
+            self.entities
+                .iter()
+                .filter_map(|entity| world.body(*entity))
+                .map(|body| Aabb::from(&body)),
+        )
+    }
+
+    // Pinned particles have no mass and don't count
+    pub fn center_of_mass(&self, world: &World) -> Option<Vec2> {
+        let mut mass = 0.0;
+        let mut weighted_position = Vec2::new();
+        for body in self
+            .entities
+            .iter()
+            .filter_map(|entity| world.body(*entity))
+        {
+            if let Mass::Value(value) = body.get_mass() {
+                mass += value;
+                weighted_position += body.position * *value;
+            }
+        }
+
+        if mass > 0.0 {
+            Some(weighted_position * (1.0 / mass))
+        } else {
+            None
+        }
+    }
+
+    pub fn remove(self, world: &mut World) {
+        for handle in self.constraints {
+            world.remove_constraint(handle);
+        }
+        if let Some(handle) = self.area_constraint {
+            world.remove_constraint(handle);
+        }
+        if let Some(handle) = self.pressure {
+            world.remove_force_generator(handle);
+        }
+        for entity in self.entities {
+            world.remove(entity);
+        }
+    }
+}
+
+// Pushes the edges of a closed counter clockwise ring outwards when its area drops below
+// `rest_area`, and pulls them in when it grows. Stays silent once any body of the ring is gone
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pressure {
+    pub entities: Vec<Entity>,
+    pub rest_area: f64,
+    // Pressure at zero area
+    pub stiffness: f64,
+}
+
+impl ForceGenerator for Pressure {
+    fn forces(&mut self, world: &World, _delta: f64) -> Vec<(Entity, Vec2)> {
+        let positions: Option<Vec<Vec2>> = self
+            .entities
+            .iter()
+            .map(|entity| world.body(*entity).map(|body| body.position))
+            .collect();
+        let Some(positions) = positions else {
+            return vec![];
+        };
+
+        let count = positions.len();
+        if count < 3 || self.rest_area <= 0.0 {
+            return vec![];
+        }
+
+        let area = AreaConstraint::area(&positions);
+        let pressure = self.stiffness * (self.rest_area - area) / self.rest_area;
+
+        // The force on an edge is its outward normal scaled by its length, split between its ends
+        let mut forces = vec![Vec2::new(); count];
+        for index in 0..count {
+            let next = (index + 1) % count;
+            let edge = positions[next] - positions[index];
+            let force = Vec2::new_at(edge.y, -edge.x) * (pressure / 2.0);
+            forces[index] += force;
+            forces[next] += force;
+        }
+
+        self.entities.iter().copied().zip(forces).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoftBodyError {
+    // A blob is a ring of at least three particles
+    TooFewSegments(usize),
+}
+
+impl fmt::Display for SoftBodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooFewSegments(segments) => {
+                write!(f, "a blob needs at least three segments, not {segments}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SoftBodyError {}
+
+pub struct BlobBuilder {
+    pub segments: usize,
+    pub particle_radius: f64,
+    pub particle_mass: f64,
+    pub softness: Softness,
+    // See `Pressure`, zero disables the force generator
+    pub pressure: f64,
+    pub area_compliance: f64,
+}
+
+impl Default for BlobBuilder {
+    fn default() -> Self {
+        Self {
+            segments: 16,
+            particle_radius: 2.0,
+            particle_mass: 1.0,
+            softness: Softness::new(5.0, 0.5),
+            pressure: 500.0,
+            area_compliance: 0.0,
+        }
+    }
+}
+
+impl BlobBuilder {
+    // A ring of particles, linked to their neighbours and to the ones after them for stiffness
+    pub fn build(
+        &self,
+        world: &mut World,
+        center: Vec2,
+        radius: f64,
+    ) -> Result<SoftBody, SoftBodyError> {
+        if self.segments < 3 {
+            return Err(SoftBodyError::TooFewSegments(self.segments));
+        }
+
+        let entities: Vec<Entity> = (0..self.segments)
+            .map(|index| {
+                let angle = 2.0 * PI * index as f64 / self.segments as f64;
+                world.add(Body::new(
+                    Shape::Circle {
+                        radius: self.particle_radius,
+                    },
+                    center + Vec2::new_polar(angle, radius),
+                    Mass::Value(self.particle_mass),
+                ))
+            })
+            .collect();
+
+        let mut soft_body = SoftBody {
+            entities,
+            ..SoftBody::default()
+        };
+
+        let count = self.segments;
+        let bending = if count > 4 { 2 } else { 1 };
+        for step in 1..=bending {
+            for index in 0..count {
+                let lhs = soft_body.entities[index];
+                let rhs = soft_body.entities[(index + step) % count];
+                soft_body.link(world, lhs, rhs, self.softness);
+            }
+        }
+
+        let positions: Vec<Vec2> = soft_body
+            .entities
+            .iter()
+            .filter_map(|entity| world.body(*entity))
+            .map(|body| body.position)
+            .collect();
+        let rest_area = AreaConstraint::area(&positions);
+
+        let mut area_constraint = AreaConstraint::new(soft_body.entities.clone(), rest_area);
+        area_constraint.compliance = self.area_compliance;
+        soft_body.area_constraint = Some(world.add_area_constraint(area_constraint));
+
+        if self.pressure > 0.0 {
+            soft_body.pressure = Some(world.add_force_generator(Box::new(Pressure {
+                entities: soft_body.entities.clone(),
+                rest_area,
+                stiffness: self.pressure,
+            })));
+        }
+
+        Ok(soft_body)
+    }
+}
+
+pub struct ClothBuilder {
+    pub columns: usize,
+    pub rows: usize,
+    pub spacing: f64,
+    pub particle_radius: f64,
+    pub particle_mass: f64,
+    pub softness: Softness,
+    // Gives the top row infinite mass so the cloth hangs from it
+    pub pin_top_row: bool,
+}
+
+impl Default for ClothBuilder {
+    fn default() -> Self {
+        Self {
+            columns: 10,
+            rows: 10,
+            spacing: 6.0,
+            particle_radius: 2.0,
+            particle_mass: 1.0,
+            softness: Softness::new(10.0, 0.7),
+            pin_top_row: true,
+        }
+    }
+}
+
+impl ClothBuilder {
+    // A grid of particles hanging down from `top_left`, with structural and shear links
+    pub fn build(&self, world: &mut World, top_left: Vec2) -> SoftBody {
+        let mut soft_body = SoftBody::default();
+
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                let mass = if self.pin_top_row && row == 0 {
+                    Mass::Infinity
+                } else {
+                    Mass::Value(self.particle_mass)
+                };
+                let offset = Vec2::new_at(column as f64, -(row as f64)) * self.spacing;
+                soft_body.entities.push(world.add(Body::new(
+                    Shape::Circle {
+                        radius: self.particle_radius,
+                    },
+                    top_left + offset,
+                    mass,
+                )));
+            }
+        }
+
+        let at = |column: usize, row: usize| row * self.columns + column;
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                let mut neighbours = vec![];
+                if column + 1 < self.columns {
+                    neighbours.push(at(column + 1, row));
+                }
+                if row + 1 < self.rows {
+                    neighbours.push(at(column, row + 1));
+                    if column + 1 < self.columns {
+                        neighbours.push(at(column + 1, row + 1));
+                    }
+                    if column > 0 {
+                        neighbours.push(at(column - 1, row + 1));
+                    }
+                }
+
+                for neighbour in neighbours {
+                    let lhs = soft_body.entities[at(column, row)];
+                    let rhs = soft_body.entities[neighbour];
+                    soft_body.link(world, lhs, rhs, self.softness);
+                }
+            }
+        }
+
+        soft_body
+    }
+}
+
+impl SoftBody {
+    // Keeps two particles at their current distance
+    fn link(&mut self, world: &mut World, lhs: Entity, rhs: Entity, softness: Softness) {
+        let (Some(lhs_body), Some(rhs_body)) = (world.body(lhs), world.body(rhs)) else {
+            return;
+        };
+
+        let mut joint = DistanceJoint::new(lhs, rhs, (rhs_body.position - lhs_body.position).len());
+        joint.softness = softness;
+        self.constraints.push(world.add_constraint(Box::new(joint)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::SolverMode;
+
+    const DELTA: std::time::Duration = std::time::Duration::from_millis(16);
+
+    fn floor(world: &mut World) -> Entity {
+        world.add(Body::new(
+            Shape::Square { edge_length: 200.0 },
+            Vec2::new_at(0.0, -100.0),
+            Mass::Infinity,
+        ))
+    }
+
+    fn drop_blob(solver_mode: SolverMode) -> (World, SoftBody, f64) {
+        let mut world = World::new();
+        world.set_solver_mode(solver_mode);
+        floor(&mut world);
+        let blob = BlobBuilder::default()
+            .build(&mut world, Vec2::new_at(0.0, 30.0), 20.0)
+            .unwrap();
+        let rest_area = world
+            .area_constraint(blob.area_constraint.unwrap())
+            .unwrap()
+            .rest_area;
+
+        for _ in 0..240 {
+            for entity in &blob.entities {
+                world.apply_force(*entity, Vec2::new_at(0.0, -100.0));
+            }
+            world.update(DELTA);
+        }
+
+        (world, blob, rest_area)
+    }
+
+    fn blob_area(world: &World, blob: &SoftBody) -> f64 {
+        let positions: Vec<Vec2> = blob
+            .entities
+            .iter()
+            .map(|entity| world.body(*entity).unwrap().position)
+            .collect();
+        AreaConstraint::area(&positions)
+    }
+
+    #[test]
+    fn test_blob_keeps_its_area() {
+        for solver_mode in [SolverMode::Impulse, SolverMode::Xpbd { substeps: 4 }] {
+            let (world, blob, rest_area) = drop_blob(solver_mode);
+
+            let area = blob_area(&world, &blob);
+            assert!(
+                area > 0.7 * rest_area,
+                "{solver_mode:?}: {area} of {rest_area}"
+            );
+            assert!(
+                area < 1.2 * rest_area,
+                "{solver_mode:?}: {area} of {rest_area}"
+            );
+
+            // Resting on the floor instead of falling through it
+            let aabb = blob.aabb(&world).unwrap();
+            assert!(aabb.bottom() > -2.0, "{solver_mode:?}: {}", aabb.bottom());
+            assert!(aabb.bottom() < 2.0, "{solver_mode:?}: {}", aabb.bottom());
+        }
+    }
+
+    #[test]
+    fn test_remove_blob() {
+        let (mut world, blob, _) = drop_blob(SolverMode::Impulse);
+        let pressure = blob.pressure.unwrap();
+        assert!(world.force_generator(pressure).is_some());
+
+        blob.remove(&mut world);
+        assert!(world.force_generator(pressure).is_none());
+        assert_eq!(0, world.force_generators().count());
+        assert_eq!(0, world.constraints().count());
+        // Only the floor is left
+        assert_eq!(1, world.ids().count());
+    }
+
+    #[test]
+    fn test_blob_needs_a_ring() {
+        let mut world = World::new();
+        let builder = BlobBuilder {
+            segments: 2,
+            ..BlobBuilder::default()
+        };
+        let result = builder.build(&mut world, Vec2::new(), 20.0);
+        assert_eq!(Some(SoftBodyError::TooFewSegments(2)), result.err());
+        assert_eq!(0, world.ids().count());
+    }
+
+    #[test]
+    fn test_cloth_as_a_unit() {
+        let mut world = World::new();
+        let builder = ClothBuilder {
+            columns: 4,
+            rows: 3,
+            ..ClothBuilder::default()
+        };
+        let cloth = builder.build(&mut world, Vec2::new_at(0.0, 50.0));
+        assert_eq!(12, cloth.entities.len());
+        // Horizontal, vertical and both diagonal links
+        assert_eq!(9 + 8 + 6 + 6, cloth.constraints.len());
+        assert!(cloth.area_constraint.is_none());
+
+        let pinned = cloth.entities[0];
+        let corner = cloth.entities[11];
+        for _ in 0..60 {
+            for entity in &cloth.entities {
+                world.apply_force(*entity, Vec2::new_at(0.0, -50.0));
+            }
+            world.update(DELTA);
+        }
+        assert_eq!(
+            Vec2::new_at(0.0, 50.0),
+            world.body(pinned).unwrap().position
+        );
+        assert!(world.body(corner).unwrap().position.y < 50.0 - 2.0 * builder.spacing + 1.0);
+
+        let center = cloth.center_of_mass(&world).unwrap();
+        cloth.translate(&mut world, Vec2::new_at(100.0, 0.0));
+        let moved = cloth.center_of_mass(&world).unwrap();
+        assert!((moved - center - Vec2::new_at(100.0, 0.0)).len() < 1e-9);
+        assert!(cloth.aabb(&world).unwrap().left() > 90.0);
+        let mut found = world.query_aabb(&cloth.aabb(&world).unwrap());
+        found.sort_by_key(Entity::index);
+        assert_eq!(cloth.entities, found);
+
+        cloth.remove(&mut world);
+        assert_eq!(0, world.ids().count());
+        assert_eq!(0, world.constraints().count());
+    }
+}
@@ -1,23 +1,61 @@
-use std::hash::Hash;
-use std::{collections::HashMap, time::Instant};
+use std::collections::{BTreeMap, BTreeSet};
 
 use crate::bsp::aabb::Aabb;
-use crate::bsp::entry::Entry;
-use crate::bsp::tree::Tree;
+use crate::bsp::broad_phase::BroadPhase;
+use crate::bsp::dynamic_tree::DynamicTree;
+use crate::collision_resolution::distance::ClosestPoints;
+use crate::collision_resolution::shape_cast::ShapeCastIntersection;
 use crate::collision_resolution::{self, CollisionEvent};
-use crate::constraint::Constraint;
-use crate::primitive::Body;
+use crate::constraint::{AreaConstraint, Constraint, ContactConstraint, UnaryConstraint};
+use crate::force_generator::ForceGenerator;
+use crate::primitive::{Body, BodySet, Mass, Shape};
 use crate::Vec2;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+#[cfg(feature = "serde")]
+use crate::constraint::{ConstraintRecord, UnaryConstraintRecord};
+#[cfg(feature = "serde")]
+use crate::force_generator::ForceGeneratorRecord;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd)]
-pub struct Entity {
-    id: usize,
+pub use crate::primitive::body_set::Entity;
+
+pub const DEFAULT_SOLVER_ITERATIONS: usize = 8;
+
+// Share of the approaching speed contacts bounce back with
+const RESTITUTION: f64 = 0.8;
+
+// Body slots a constraint acts on, `None` when one of its bodies is gone or the constraint
+// is solved in the other phase of the step
+type ConstraintSlots = Option<Vec<usize>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SolverMode {
+    // Forces and sequential impulses on the velocities, one integration per step
+    Impulse,
+    // Extended position based dynamics, constraints and contacts are projected on the
+    // positions with their compliance in every substep
+    Xpbd { substeps: usize },
 }
 
+// Broadphase ids are the slots of the bodies
+#[derive(Clone)]
 pub struct World {
-    bodies: HashMap<Entity, Body>,
-    constraints: Vec<Box<dyn Constraint>>,
-    last_id: usize,
+    bodies: BodySet,
+    // Ordered by handle, so constraints get solved in the order they were added
+    constraints: BTreeMap<ConstraintHandle, ConstraintEntry>,
+    last_constraint_id: usize,
+    // Ordered by handle like the constraints
+    force_generators: BTreeMap<ForceGeneratorHandle, Box<dyn ForceGenerator>>,
+    last_force_generator_id: usize,
+    broadphase: Box<dyn BroadPhase>,
+    solver_iterations: usize,
+    solver_mode: SolverMode,
+    // Compliance of the contacts in `SolverMode::Xpbd`, zero keeps them rigid
+    contact_compliance: f64,
+    // Pairs skipped by the narrowphase, lower index first. Ordered rather than hashed, so
+    // walking them goes the same way in every process
+    disabled_collisions: BTreeSet<(Entity, Entity)>,
 }
 
 pub struct CollisionData {
@@ -25,85 +63,613 @@ pub struct CollisionData {
     pub collision: CollisionEvent,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConstraintHandle {
+    id: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ForceGeneratorHandle {
+    id: usize,
+}
+
+// Every kind of constraint shares the handles, loads and break thresholds of the world
+#[derive(Clone)]
+pub enum ConstraintKind {
+    Pair(Box<dyn Constraint>),
+    Unary(Box<dyn UnaryConstraint>),
+    Area(AreaConstraint),
+}
+
+#[derive(Clone)]
+struct ConstraintEntry {
+    kind: ConstraintKind,
+    break_threshold: Option<f64>,
+    // Largest force the constraint applied during the last step, impulses count
+    // as force per step
+    load: f64,
+}
+
+// Dispatches the solver phases to the kind of constraint, `slots` hold the bodies in
+// the order of `entities`
+impl ConstraintEntry {
+    fn entities(&self) -> Vec<Entity> {
+        match &self.kind {
+            ConstraintKind::Pair(constraint) => {
+                vec![constraint.get_first_entity(), constraint.get_second_entity()]
+            }
+            ConstraintKind::Unary(constraint) => vec![constraint.get_entity()],
+            ConstraintKind::Area(constraint) => constraint.entities.clone(),
+        }
+    }
+
+    fn is_broken(&self) -> bool {
+        self.break_threshold.is_some_and(|threshold| self.load > threshold)
+    }
+
+    fn is_position_based(&self) -> bool {
+        match &self.kind {
+            ConstraintKind::Pair(constraint) => constraint.is_position_based(),
+            ConstraintKind::Unary(_) => false,
+            ConstraintKind::Area(_) => true,
+        }
+    }
+
+    fn process(&self, bodies: &mut BodySet, slots: &[usize], delta: f64) {
+        if let ConstraintKind::Pair(constraint) = &self.kind {
+            bodies.with_pair(slots[0], slots[1], |lhs, rhs| {
+                constraint.process(lhs, rhs, delta)
+            });
+        }
+    }
+
+    fn prepare(&mut self, bodies: &BodySet, slots: &[usize], delta: f64) {
+        match &mut self.kind {
+            ConstraintKind::Pair(constraint) => {
+                let (lhs, rhs) = (bodies.body_at(slots[0]), bodies.body_at(slots[1]));
+                constraint.prepare(&lhs, &rhs, delta);
+            }
+            ConstraintKind::Unary(constraint) => {
+                constraint.prepare(&bodies.body_at(slots[0]), delta)
+            }
+            ConstraintKind::Area(constraint) => constraint.prepare(),
+        }
+    }
+
+    fn solve_velocity(&mut self, bodies: &mut BodySet, slots: &[usize], delta: f64) {
+        match &mut self.kind {
+            ConstraintKind::Pair(constraint) => {
+                bodies.with_pair(slots[0], slots[1], |lhs, rhs| {
+                    constraint.solve_velocity(lhs, rhs, delta)
+                });
+            }
+            ConstraintKind::Unary(constraint) => {
+                let mut body = bodies.body_at(slots[0]);
+                constraint.solve_velocity(&mut body, delta);
+                bodies.store(slots[0], &body);
+            }
+            ConstraintKind::Area(_) => {}
+        }
+    }
+
+    fn solve_position(&mut self, bodies: &mut BodySet, slots: &[usize], delta: f64) {
+        match &mut self.kind {
+            ConstraintKind::Pair(constraint) => {
+                bodies.with_pair(slots[0], slots[1], |lhs, rhs| {
+                    constraint.solve_position(lhs, rhs, delta)
+                });
+            }
+            ConstraintKind::Unary(_) => {}
+            ConstraintKind::Area(constraint) => {
+                let mut polygon: Vec<Body> =
+                    slots.iter().map(|slot| bodies.body_at(*slot)).collect();
+                constraint.solve_position(&mut polygon, delta);
+                for (slot, body) in slots.iter().zip(&polygon) {
+                    bodies.store(*slot, body);
+                }
+            }
+        }
+    }
+
+    fn applied_impulse(&self) -> f64 {
+        match &self.kind {
+            ConstraintKind::Pair(constraint) => constraint.applied_impulse(),
+            ConstraintKind::Unary(constraint) => constraint.applied_impulse(),
+            ConstraintKind::Area(constraint) => constraint.applied_impulse(),
+        }
+    }
+}
+
+// Full copy of a world, stepping a restored world repeats the original bit for bit.
+// Contacts are detected anew every step, so there is no contact state besides the bodies
+#[derive(Clone)]
+pub struct WorldSnapshot {
+    world: World,
+}
+
+pub enum WorldEvent {
+    Collision(CollisionData),
+    ConstraintBroken { handle: ConstraintHandle, load: f64 },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RayHit {
+    pub entity: Entity,
+    pub point: Vec2,
+    pub normal: Vec2,
+    // Hit parameter along the ray direction, i.e. `point == origin + dir * fraction`
+    pub fraction: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ShapeCastHit {
+    pub entity: Entity,
+    pub intersection: ShapeCastIntersection,
+}
+
 impl World {
     pub fn new() -> Self {
+        Self::with_broadphase(Box::new(DynamicTree::default()))
+    }
+
+    pub fn with_broadphase(broadphase: Box<dyn BroadPhase>) -> Self {
         Self {
-            bodies: HashMap::new(),
-            constraints: vec![],
-            last_id: 0,
+            bodies: BodySet::new(),
+            constraints: BTreeMap::new(),
+            last_constraint_id: 0,
+            force_generators: BTreeMap::new(),
+            last_force_generator_id: 0,
+            broadphase,
+            solver_iterations: DEFAULT_SOLVER_ITERATIONS,
+            solver_mode: SolverMode::Impulse,
+            contact_compliance: 0.0,
+            disabled_collisions: BTreeSet::new(),
+        }
+    }
+
+    pub fn snapshot(&self) -> WorldSnapshot {
+        WorldSnapshot {
+            world: self.clone(),
+        }
+    }
+
+    // Entities and constraint handles get handed out again exactly like after the snapshot
+    pub fn restore(&mut self, snapshot: &WorldSnapshot) {
+        *self = snapshot.world.clone();
+    }
+
+    // FNV-1a over the bits of every body's state, for lockstep peers to compare. Matches
+    // across platforms only with the `deterministic` feature
+    pub fn state_hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for (entity, body) in self.entities() {
+            let state = [
+                body.position.x,
+                body.position.y,
+                body.velocity.x,
+                body.velocity.y,
+                body.angle,
+                body.angular_velocity,
+            ];
+            let words = std::iter::once(entity.index() as u64).chain(state.map(f64::to_bits));
+            for byte in words.flat_map(u64::to_le_bytes) {
+                hash ^= u64::from(byte);
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+        hash
+    }
+
+    // Moves every body over into `broadphase`
+    pub fn set_broadphase(&mut self, broadphase: Box<dyn BroadPhase>) {
+        self.broadphase = broadphase;
+        for index in self.bodies.slots() {
+            self.broadphase.insert(index, Aabb::from(&self.bodies.body_at(index)));
         }
     }
 
     pub fn add(&mut self, body: Body) -> Entity {
-        self.last_id += 1;
-        self.bodies.insert(Entity { id: self.last_id }, body);
-        Entity { id: self.last_id }
+        let entity = self.bodies.insert(body);
+        self.broadphase.insert(entity.index(), Aabb::from(&body));
+        entity
+    }
+
+    // Panics when both ends are the same body. Callers building constraints from outside
+    // input, like `RopeBuilder` and `Scene::build`, reject those with an error beforehand
+    pub fn add_constraint(&mut self, constraint: Box<dyn Constraint>) -> ConstraintHandle {
+        assert_ne!(
+            constraint.get_first_entity(),
+            constraint.get_second_entity(),
+            "A constraint needs two different bodies!"
+        );
+        self.insert_constraint(ConstraintKind::Pair(constraint))
+    }
+
+    pub fn add_unary_constraint(
+        &mut self,
+        constraint: Box<dyn UnaryConstraint>,
+    ) -> ConstraintHandle {
+        self.insert_constraint(ConstraintKind::Unary(constraint))
+    }
+
+    // Only solved in `SolverMode::Xpbd`
+    pub fn add_area_constraint(&mut self, constraint: AreaConstraint) -> ConstraintHandle {
+        self.insert_constraint(ConstraintKind::Area(constraint))
+    }
+
+    fn insert_constraint(&mut self, kind: ConstraintKind) -> ConstraintHandle {
+        self.last_constraint_id += 1;
+        let handle = ConstraintHandle {
+            id: self.last_constraint_id,
+        };
+        self.constraints.insert(
+            handle,
+            ConstraintEntry {
+                kind,
+                break_threshold: None,
+                load: 0.0,
+            },
+        );
+        handle
+    }
+
+    // Removes a constraint of any kind
+    pub fn remove_constraint(&mut self, handle: ConstraintHandle) -> Option<ConstraintKind> {
+        self.constraints.remove(&handle).map(|entry| entry.kind)
+    }
+
+    // Every constraint in the order they were added, whatever their kind
+    pub fn constraints(&self) -> impl Iterator<Item = (ConstraintHandle, &ConstraintKind)> {
+        self.constraints
+            .iter()
+            .map(|(handle, entry)| (*handle, &entry.kind))
+    }
+
+    pub fn constraint(&self, handle: ConstraintHandle) -> Option<&dyn Constraint> {
+        match &self.constraints.get(&handle)?.kind {
+            ConstraintKind::Pair(constraint) => Some(constraint.as_ref()),
+            _ => None,
+        }
+    }
+
+    pub fn constraint_mut(&mut self, handle: ConstraintHandle) -> Option<&mut dyn Constraint> {
+        match &mut self.constraints.get_mut(&handle)?.kind {
+            ConstraintKind::Pair(constraint) => Some(constraint.as_mut()),
+            _ => None,
+        }
+    }
+
+    pub fn unary_constraint(&self, handle: ConstraintHandle) -> Option<&dyn UnaryConstraint> {
+        match &self.constraints.get(&handle)?.kind {
+            ConstraintKind::Unary(constraint) => Some(constraint.as_ref()),
+            _ => None,
+        }
+    }
+
+    pub fn unary_constraint_mut(
+        &mut self,
+        handle: ConstraintHandle,
+    ) -> Option<&mut dyn UnaryConstraint> {
+        match &mut self.constraints.get_mut(&handle)?.kind {
+            ConstraintKind::Unary(constraint) => Some(constraint.as_mut()),
+            _ => None,
+        }
+    }
+
+    pub fn area_constraint(&self, handle: ConstraintHandle) -> Option<&AreaConstraint> {
+        match &self.constraints.get(&handle)?.kind {
+            ConstraintKind::Area(constraint) => Some(constraint),
+            _ => None,
+        }
+    }
+
+    // Removes the constraint once its load exceeds `threshold`, see `constraint_load`
+    pub fn set_break_threshold(&mut self, handle: ConstraintHandle, threshold: Option<f64>) {
+        if let Some(entry) = self.constraints.get_mut(&handle) {
+            entry.break_threshold = threshold;
+        }
+    }
+
+    pub fn constraint_load(&self, handle: ConstraintHandle) -> Option<f64> {
+        Some(self.constraints.get(&handle)?.load)
+    }
+
+    pub const fn solver_iterations(&self) -> usize {
+        self.solver_iterations
+    }
+
+    pub fn set_solver_iterations(&mut self, solver_iterations: usize) {
+        self.solver_iterations = solver_iterations;
+    }
+
+    pub const fn solver_mode(&self) -> SolverMode {
+        self.solver_mode
+    }
+
+    pub fn set_solver_mode(&mut self, solver_mode: SolverMode) {
+        self.solver_mode = solver_mode;
+    }
+
+    pub const fn contact_compliance(&self) -> f64 {
+        self.contact_compliance
+    }
+
+    pub fn set_contact_compliance(&mut self, contact_compliance: f64) {
+        self.contact_compliance = contact_compliance;
+    }
+
+    pub fn add_force_generator(
+        &mut self,
+        force_generator: Box<dyn ForceGenerator>,
+    ) -> ForceGeneratorHandle {
+        self.last_force_generator_id += 1;
+        let handle = ForceGeneratorHandle {
+            id: self.last_force_generator_id,
+        };
+        self.force_generators.insert(handle, force_generator);
+        handle
+    }
+
+    pub fn remove_force_generator(
+        &mut self,
+        handle: ForceGeneratorHandle,
+    ) -> Option<Box<dyn ForceGenerator>> {
+        self.force_generators.remove(&handle)
     }
 
-    pub fn add_constraint(&mut self, constraint: Box<dyn Constraint>) 
-    {
-        self.constraints.push(constraint);
+    pub fn force_generator(&self, handle: ForceGeneratorHandle) -> Option<&dyn ForceGenerator> {
+        self.force_generators.get(&handle).map(Box::as_ref)
+    }
+
+    pub fn force_generators(&self) -> impl Iterator<Item = ForceGeneratorHandle> + '_ {
+        self.force_generators.keys().copied()
     }
 
     pub fn remove(&mut self, entity: Entity) {
-        self.bodies.remove(&entity);
+        // A stale handle must not remove whoever reused its slot
+        if self.bodies.remove(entity).is_some() {
+            self.broadphase.remove(entity.index());
+            self.disabled_collisions.retain(|(lhs, rhs)| *lhs != entity && *rhs != entity);
+        }
+    }
+
+    // Lets the two bodies pass through each other, e.g. adjacent links of a chain
+    pub fn disable_collision(&mut self, lhs: Entity, rhs: Entity) {
+        if lhs != rhs && self.bodies.contains(lhs) && self.bodies.contains(rhs) {
+            self.disabled_collisions.insert(Self::collision_key(lhs, rhs));
+        }
+    }
+
+    pub fn enable_collision(&mut self, lhs: Entity, rhs: Entity) {
+        self.disabled_collisions.remove(&Self::collision_key(lhs, rhs));
+    }
+
+    pub fn is_collision_disabled(&self, lhs: Entity, rhs: Entity) -> bool {
+        self.disabled_collisions.contains(&Self::collision_key(lhs, rhs))
+    }
+
+    fn collision_key(lhs: Entity, rhs: Entity) -> (Entity, Entity) {
+        if lhs.index() <= rhs.index() {
+            (lhs, rhs)
+        } else {
+            (rhs, lhs)
+        }
     }
 
     pub fn apply_force(&mut self, entity: Entity, force: Vec2) {
-        let body = self.bodies.get_mut(&entity);
-        if let Some(body) = body {
-            body.force += force;
+        if let Some(index) = self.bodies.slot(entity) {
+            self.bodies.forces[index] += force;
         }
     }
 
-    pub fn body(&self, entity: Entity) -> Option<&Body> {
-        self.bodies.get(&entity)
+    pub fn apply_torque(&mut self, entity: Entity, torque: f64) {
+        if let Some(index) = self.bodies.slot(entity) {
+            self.bodies.torques[index] += torque;
+        }
     }
 
-    pub fn bodies(&self) -> impl Iterator<Item = &Body> {
-        self.bodies.values()
+    // Shape and mass stay untouched, everything else gets written back
+    pub fn with_body_mut<R>(
+        &mut self,
+        entity: Entity,
+        f: impl FnOnce(&mut Body) -> R,
+    ) -> Option<R> {
+        let index = self.bodies.slot(entity)?;
+        let mut body = self.bodies.body_at(index);
+        let result = f(&mut body);
+        self.bodies.store(index, &body);
+        self.broadphase.update(index, Aabb::from(&body));
+        Some(result)
     }
 
-    pub fn entities(&self) -> impl Iterator<Item = (&Entity, &Body)> {
-        self.bodies.iter()
+    pub fn body(&self, entity: Entity) -> Option<Body> {
+        self.bodies.get(entity)
+    }
+
+    pub fn bodies(&self) -> impl Iterator<Item = Body> + '_ {
+        self.bodies.slots().map(|index| self.bodies.body_at(index))
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item = (Entity, Body)> + '_ {
+        self.bodies
+            .slots()
+            .map(|index| (self.bodies.entity(index), self.bodies.body_at(index)))
     }
 
     pub fn ids(&self) -> impl Iterator<Item = Entity> + '_ {
-        self.bodies.keys().copied()
+        self.bodies.entities()
     }
 
-    pub fn update(&mut self, dt: std::time::Duration) -> Vec<CollisionData> {
-        let delta = dt.as_secs_f64();
+    pub fn closest_points(&self, lhs: Entity, rhs: Entity) -> Option<ClosestPoints> {
+        let lhs = self.bodies.get(lhs)?;
+        let rhs = self.bodies.get(rhs)?;
+        Some(collision_resolution::closest_points(&lhs, &rhs))
+    }
 
-        for constraint in &self.constraints {
-            let entity_refs: [&Entity; 2] = [&constraint.get_first_entity(), &constraint.get_second_entity()];
-            let [lhs, rhs] = self.bodies.get_many_mut(entity_refs).unwrap();
+    pub fn distance(&self, lhs: Entity, rhs: Entity) -> Option<f64> {
+        self.closest_points(lhs, rhs).map(|points| points.distance)
+    }
+
+    pub fn query_point(&self, point: Vec2) -> Vec<Entity> {
+        self.broadphase
+            .query_aabb(&Aabb::from_point(&point))
+            .into_iter()
+            .filter(|id| collision_resolution::contains_point(&self.bodies.body_at(*id), &point))
+            .map(|id| self.bodies.entity(id))
+            .collect()
+    }
+
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<Entity> {
+        self.broadphase
+            .query_aabb(aabb)
+            .into_iter()
+            .filter(|id| Aabb::from(&self.bodies.body_at(*id)).intersects(aabb))
+            .map(|id| self.bodies.entity(id))
+            .collect()
+    }
+
+    pub fn intersect_shape(&self, shape: Shape, position: Vec2) -> Vec<Entity> {
+        let mut probe = Body::new(shape, position, Mass::Infinity);
+        self.broadphase
+            .query_aabb(&Aabb::from(&probe))
+            .into_iter()
+            .filter(|id| {
+                let mut body = self.bodies.body_at(*id);
+                // Concentric shapes always overlap, and the narrowphase can not derive a
+                // normal for them
+                body.position == probe.position
+                    || collision_resolution::detect_collision(&mut probe, &mut body).is_some()
+            })
+            .map(|id| self.bodies.entity(id))
+            .collect()
+    }
+
+    pub fn cast_ray(
+        &self,
+        origin: Vec2,
+        dir: Vec2,
+        max_toi: f64,
+        filter: impl Fn(Entity, &Body) -> bool,
+    ) -> Option<RayHit> {
+        let mut candidates = self.broadphase.ray_candidates(&origin, &dir, max_toi);
+        candidates.sort_by(|lhs, rhs| lhs.1.total_cmp(&rhs.1));
 
-            constraint.process(lhs, rhs, delta);
+        let mut closest_hit: Option<RayHit> = None;
+        for (id, aabb_toi) in candidates {
+            if let Some(hit) = &closest_hit {
+                if hit.fraction < aabb_toi {
+                    break;
+                }
+            }
+
+            let entity = self.bodies.entity(id);
+            let body = self.bodies.body_at(id);
+            if !filter(entity, &body) {
+                continue;
+            }
+
+            let max_toi = closest_hit.map_or(max_toi, |hit| hit.fraction);
+            if let Some(intersection) =
+                collision_resolution::cast_ray(&body, &origin, &dir, max_toi)
+            {
+                closest_hit = Some(RayHit {
+                    entity,
+                    point: origin + dir * intersection.toi,
+                    normal: intersection.normal,
+                    fraction: intersection.toi,
+                });
+            }
         }
-        
-        for body in self.bodies.values_mut() {
-            body.position += body.velocity * delta;
 
-            let inverse_mass = body.get_mass().get_inverse();
-            body.acceleration = body.force * inverse_mass;
+        closest_hit
+    }
+
+    pub fn cast_shape(
+        &self,
+        shape: Shape,
+        from: Vec2,
+        displacement: Vec2,
+        filter: impl Fn(Entity, &Body) -> bool,
+    ) -> Option<ShapeCastHit> {
+        let start_aabb = Aabb::from(&Body::new(shape, from, Mass::Infinity));
+        let end_aabb = Aabb::from(&Body::new(shape, from + displacement, Mass::Infinity));
+        let swept_aabb = start_aabb.merge(&end_aabb);
+
+        let mut closest_hit: Option<ShapeCastHit> = None;
+        for id in self.broadphase.query_aabb(&swept_aabb) {
+            let entity = self.bodies.entity(id);
+            let body = self.bodies.body_at(id);
+            if !filter(entity, &body) {
+                continue;
+            }
 
-            body.velocity += body.acceleration * delta;
-            body.velocity *= body.friction.powf(delta);
+            if let Some(intersection) =
+                collision_resolution::cast_shape(&shape, &from, &displacement, &body)
+            {
+                if closest_hit.is_none_or(|hit| intersection.toi < hit.intersection.toi) {
+                    closest_hit = Some(ShapeCastHit {
+                        entity,
+                        intersection,
+                    });
+                }
+            }
         }
 
-        let collision_data = self.resolve_collisions();
-        for collision in &collision_data {
-            let ids: [&Entity; 2] = [&collision.ids[0], &collision.ids[1]];
-            let [lhs, rhs] = self.bodies.get_many_mut(ids).unwrap();
-            Self::resolve_contact_velocity(lhs, rhs, &collision.collision.normal);
+        closest_hit
+    }
+
+    pub fn update(&mut self, dt: std::time::Duration) -> Vec<WorldEvent> {
+        let delta = dt.as_secs_f64();
+
+        let mut force_generators = std::mem::take(&mut self.force_generators);
+        for force_generator in force_generators.values_mut() {
+            for (entity, force) in force_generator.forces(self, delta) {
+                self.apply_force(entity, force);
+            }
         }
+        self.force_generators = force_generators;
 
-        for body in self.bodies.values_mut() {
-            body.force = Vec2::default();
+        let (velocity_slots, position_slots) = self.split_constraint_slots();
+        for (entry, slots) in self.constraints.values_mut().zip(&velocity_slots) {
+            entry.load = 0.0;
+            if let Some(slots) = slots {
+                let force = self.bodies.forces[slots[0]];
+                entry.process(&mut self.bodies, slots, delta);
+                entry.load = (self.bodies.forces[slots[0]] - force).len();
+            }
         }
 
-        collision_data
+        let collision_data = match self.solver_mode {
+            SolverMode::Impulse => {
+                // Joints fix up the velocities before they move the bodies
+                self.solve_constraints(&velocity_slots, delta);
+                self.bodies.integrate(delta);
+
+                let collision_data = self.resolve_collisions();
+                for collision in &collision_data {
+                    let [lhs, rhs] = collision.ids.map(|entity| entity.index());
+                    self.bodies.with_pair(lhs, rhs, |lhs, rhs| {
+                        Self::resolve_contact_velocity(lhs, rhs, &collision.collision.normal)
+                    });
+                }
+                collision_data
+            }
+            SolverMode::Xpbd { substeps } => {
+                self.step_xpbd(&velocity_slots, &position_slots, delta, substeps.max(1))
+            }
+        };
+        let mut events = self.break_constraints();
+
+        self.bodies.forces.fill(Vec2::default());
+        self.bodies.torques.fill(0.0);
+
+        // Keep the broadphase valid for queries in between steps
+        self.update_broadphase();
+
+        events.extend(collision_data.into_iter().map(WorldEvent::Collision));
+        events
     }
 
     fn calculate_separating_velocity(
@@ -122,8 +688,7 @@ impl World {
             return;
         }
 
-        let restitution = 0.8;
-        let new_separating_velocity = -separating_velocity * restitution;
+        let new_separating_velocity = -separating_velocity * RESTITUTION;
         let delta_velocity = new_separating_velocity - separating_velocity;
 
         let lhs_inverse_mass = lhs.get_mass().get_inverse();
@@ -141,41 +706,1037 @@ impl World {
         rhs.velocity += impulse_per_mass.scaled(-rhs_inverse_mass);
     }
 
-    fn resolve_collisions(&mut self) -> Vec<CollisionData> {
+    // Slots of the constraints solved on the velocities and of those solved on the positions
+    fn split_constraint_slots(&self) -> (Vec<ConstraintSlots>, Vec<ConstraintSlots>) {
+        let xpbd = matches!(self.solver_mode, SolverMode::Xpbd { .. });
+        self.constraints
+            .values()
+            .map(|entry| {
+                let slots: ConstraintSlots = entry
+                    .entities()
+                    .iter()
+                    .map(|entity| self.bodies.slot(*entity))
+                    .collect();
+                if xpbd && entry.is_position_based() {
+                    (None, slots)
+                } else {
+                    (slots, None)
+                }
+            })
+            .unzip()
+    }
+
+    fn step_xpbd(
+        &mut self,
+        velocity_slots: &[ConstraintSlots],
+        position_slots: &[ConstraintSlots],
+        delta: f64,
+        substeps: usize,
+    ) -> Vec<CollisionData> {
+        let substep = delta / substeps as f64;
         let mut collisions: Vec<CollisionData> = vec![];
-        let timer = Instant::now();
 
-        let entries: Vec<_> = self
-            .bodies
+        for _ in 0..substeps {
+            self.bodies.integrate_velocities(substep);
+            self.solve_constraints(velocity_slots, substep);
+
+            let previous_positions = self.bodies.positions.clone();
+            self.bodies.integrate_positions(substep);
+
+            for (entry, slots) in self.constraints.values_mut().zip(position_slots) {
+                if entry.is_broken() {
+                    continue;
+                }
+                if let Some(slots) = slots {
+                    entry.prepare(&self.bodies, slots, substep);
+                    entry.solve_position(&mut self.bodies, slots, substep);
+                    let load = entry.applied_impulse() / substep;
+                    entry.load = entry.load.max(load);
+                }
+            }
+
+            // Contacts are position constraints of their own, found anew every substep
+            let substep_collisions = self.detect_collisions();
+            let mut contacts: Vec<ContactConstraint> = vec![];
+            for collision in &substep_collisions {
+                let [lhs, rhs] = collision.ids.map(|entity| entity.index());
+                let (lhs_body, rhs_body) = (self.bodies.body_at(lhs), self.bodies.body_at(rhs));
+                let mut contact = ContactConstraint::new(
+                    &lhs_body,
+                    &rhs_body,
+                    &collision.collision,
+                    self.contact_compliance,
+                );
+                contact.restitution = RESTITUTION;
+                self.bodies.with_pair(lhs, rhs, |lhs, rhs| {
+                    contact.solve_position(lhs, rhs, substep)
+                });
+                contacts.push(contact);
+            }
+
+            for (index, previous_position) in previous_positions.iter().enumerate() {
+                let displacement = self.bodies.positions[index] - *previous_position;
+                self.bodies.velocities[index] = displacement * (1.0 / substep);
+            }
+
+            for (collision, contact) in substep_collisions.iter().zip(&contacts) {
+                let [lhs, rhs] = collision.ids.map(|entity| entity.index());
+                self.bodies.with_pair(lhs, rhs, |lhs, rhs| {
+                    contact.solve_velocity(lhs, rhs, substep)
+                });
+            }
+
+            // Report every touching pair once, with its latest contact
+            for collision in substep_collisions {
+                match collisions.iter_mut().find(|other| other.ids == collision.ids) {
+                    Some(other) => *other = collision,
+                    None => collisions.push(collision),
+                }
+            }
+        }
+
+        collisions
+    }
+
+    fn solve_constraints(&mut self, slots: &[ConstraintSlots], delta: f64) {
+        for (entry, slots) in self.constraints.values_mut().zip(slots) {
+            if let Some(slots) = slots {
+                entry.prepare(&self.bodies, slots, delta);
+            }
+        }
+
+        // Velocity changes every breakable constraint applied to its bodies so far
+        let mut applied: Vec<Vec<(Vec2, f64)>> = slots
             .iter()
-            .map(|(k, v)| Entry {
-                id: k.id,
-                aabb: Aabb::from(v),
-            })
+            .map(|slots| vec![(Vec2::default(), 0.0); slots.as_ref().map_or(0, Vec::len)])
             .collect();
+        for _ in 0..self.solver_iterations {
+            let entries = self.constraints.values_mut().zip(slots).zip(&mut applied);
+            for ((entry, slots), applied) in entries {
+                let Some(slots) = slots else {
+                    continue;
+                };
+                if entry.is_broken() {
+                    continue;
+                }
+                let Some(threshold) = entry.break_threshold else {
+                    entry.solve_velocity(&mut self.bodies, slots, delta);
+                    continue;
+                };
 
-        let bsp_tree = Tree::new_with(entries);
+                let before = Self::slot_velocities(&self.bodies, slots);
+                entry.solve_velocity(&mut self.bodies, slots, delta);
+                let after = Self::slot_velocities(&self.bodies, slots);
+                for ((total, before), after) in applied.iter_mut().zip(before).zip(after) {
+                    total.0 += after.0 - before.0;
+                    total.1 += after.1 - before.1;
+                }
 
-        let potential_collisions = bsp_tree.internal_collisions();
-        for (lhs, rhs) in potential_collisions {
-            let ids = [Entity { id: lhs }, Entity { id: rhs }];
-            let id_refs = [&ids[0], &ids[1]];
-            let [lhs, rhs] = self.bodies.get_many_mut(id_refs).unwrap();
-            let collision = collision_resolution::detect_collision(lhs, rhs);
-            if let Some(collision) = collision {
-                collisions.push(CollisionData { ids, collision });
+                entry.load = entry.load.max(entry.applied_impulse() / delta);
+                if entry.load > threshold {
+                    // Gives way at the threshold, the rest of its impulse never reaches the bodies
+                    let excess = 1.0 - threshold / entry.load;
+                    for (slot, (velocity, angular_velocity)) in slots.iter().zip(applied.iter()) {
+                        self.bodies.velocities[*slot] -= *velocity * excess;
+                        self.bodies.angular_velocities[*slot] -= angular_velocity * excess;
+                    }
+                }
             }
         }
 
-        for collision in &collisions {
-            let ids = [&collision.ids[0], &collision.ids[1]];
-            let [lhs, rhs] = self.bodies.get_many_mut(ids).unwrap();
-            collision_resolution::resolve_collision(lhs, rhs, &collision.collision);
+        for (entry, slots) in self.constraints.values_mut().zip(slots) {
+            if slots.is_some() {
+                entry.load = entry.load.max(entry.applied_impulse() / delta);
+            }
+        }
+    }
+
+    fn slot_velocities(bodies: &BodySet, slots: &[usize]) -> Vec<(Vec2, f64)> {
+        slots
+            .iter()
+            .map(|slot| (bodies.velocities[*slot], bodies.angular_velocities[*slot]))
+            .collect()
+    }
+
+    // Constraints that broke during the step are only removed here, the solvers skip them
+    // as soon as their load passes the threshold
+    fn break_constraints(&mut self) -> Vec<WorldEvent> {
+        let mut events = vec![];
+        self.constraints.retain(|handle, entry| {
+            let broken = entry.is_broken();
+            if broken {
+                events.push(WorldEvent::ConstraintBroken {
+                    handle: *handle,
+                    load: entry.load,
+                });
+            }
+            !broken
+        });
+        events
+    }
+
+    fn update_broadphase(&mut self) {
+        for index in self.bodies.slots() {
+            self.broadphase.update(index, Aabb::from(&self.bodies.body_at(index)));
+        }
+    }
+
+    fn detect_collisions(&mut self) -> Vec<CollisionData> {
+        self.update_broadphase();
+
+        let mut potential_collisions = self.broadphase.candidate_pairs();
+        if !self.disabled_collisions.is_empty() {
+            potential_collisions.retain(|(lhs, rhs)| {
+                let key = (self.bodies.entity(*lhs), self.bodies.entity(*rhs));
+                !self.disabled_collisions.contains(&key)
+            });
         }
+        Self::narrowphase(&self.bodies, &potential_collisions)
+    }
 
-        let duration = timer.elapsed();
-        println!("{}us", duration.as_micros());
+    fn resolve_collisions(&mut self) -> Vec<CollisionData> {
+        let collisions = self.detect_collisions();
+
+        // Resolution stays serial and in canonical pair order, so every build moves
+        // the bodies the same way
+        for collision in &collisions {
+            let [lhs, rhs] = collision.ids.map(|entity| entity.index());
+            self.bodies.with_pair(lhs, rhs, |lhs, rhs| {
+                collision_resolution::resolve_collision(lhs, rhs, &collision.collision)
+            });
+        }
 
         collisions
     }
+
+    fn detect_pair(bodies: &BodySet, pair: &(usize, usize)) -> Option<CollisionData> {
+        let ids = [bodies.entity(pair.0), bodies.entity(pair.1)];
+        // Detection never moves the bodies, so it can work on copies
+        let (mut lhs, mut rhs) = (bodies.body_at(pair.0), bodies.body_at(pair.1));
+        let collision = collision_resolution::detect_collision(&mut lhs, &mut rhs)?;
+        Some(CollisionData { ids, collision })
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn narrowphase(bodies: &BodySet, pairs: &[(usize, usize)]) -> Vec<CollisionData> {
+        pairs.iter().filter_map(|pair| Self::detect_pair(bodies, pair)).collect()
+    }
+
+    #[cfg(feature = "parallel")]
+    fn narrowphase(bodies: &BodySet, pairs: &[(usize, usize)]) -> Vec<CollisionData> {
+        // Collecting keeps the order of `pairs`
+        pairs.par_iter().filter_map(|pair| Self::detect_pair(bodies, pair)).collect()
+    }
+}
+
+// Boxed constraints and force generators get saved through their registries. The broadphase
+// only caches the bodies, a loaded world starts out with the default one
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct WorldRecord {
+    bodies: BodySet,
+    constraints: Vec<ConstraintEntryRecord>,
+    last_constraint_id: usize,
+    force_generators: Vec<(ForceGeneratorHandle, ForceGeneratorRecord)>,
+    last_force_generator_id: usize,
+    solver_iterations: usize,
+    solver_mode: SolverMode,
+    contact_compliance: f64,
+    disabled_collisions: Vec<(Entity, Entity)>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ConstraintEntryRecord {
+    handle: ConstraintHandle,
+    kind: ConstraintKindRecord,
+    break_threshold: Option<f64>,
+    load: f64,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+enum ConstraintKindRecord {
+    Pair(ConstraintRecord),
+    Unary(UnaryConstraintRecord),
+    Area(AreaConstraint),
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for World {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::Error;
+        let unregistered =
+            |kind| S::Error::custom(format!("{kind} type missing from the registry"));
+
+        let constraints = self
+            .constraints
+            .iter()
+            .map(|(handle, entry)| {
+                let kind = match &entry.kind {
+                    ConstraintKind::Pair(constraint) => {
+                        ConstraintRecord::from_dyn(constraint.as_ref())
+                            .map(ConstraintKindRecord::Pair)
+                            .ok_or_else(|| unregistered("constraint"))?
+                    }
+                    ConstraintKind::Unary(constraint) => {
+                        UnaryConstraintRecord::from_dyn(constraint.as_ref())
+                            .map(ConstraintKindRecord::Unary)
+                            .ok_or_else(|| unregistered("unary constraint"))?
+                    }
+                    ConstraintKind::Area(constraint) => {
+                        ConstraintKindRecord::Area(constraint.clone())
+                    }
+                };
+                Ok(ConstraintEntryRecord {
+                    handle: *handle,
+                    kind,
+                    break_threshold: entry.break_threshold,
+                    load: entry.load,
+                })
+            })
+            .collect::<Result<_, S::Error>>()?;
+        let force_generators = self
+            .force_generators
+            .iter()
+            .map(|(handle, generator)| {
+                let record = ForceGeneratorRecord::from_dyn(generator.as_ref())
+                    .ok_or_else(|| unregistered("force generator"))?;
+                Ok((*handle, record))
+            })
+            .collect::<Result<_, S::Error>>()?;
+
+        let disabled_collisions: Vec<(Entity, Entity)> =
+            self.disabled_collisions.iter().copied().collect();
+
+        WorldRecord {
+            bodies: self.bodies.clone(),
+            constraints,
+            last_constraint_id: self.last_constraint_id,
+            force_generators,
+            last_force_generator_id: self.last_force_generator_id,
+            solver_iterations: self.solver_iterations,
+            solver_mode: self.solver_mode,
+            contact_compliance: self.contact_compliance,
+            disabled_collisions,
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for World {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let record = WorldRecord::deserialize(deserializer)?;
+
+        let mut world = World::new();
+        world.bodies = record.bodies;
+        world.set_broadphase(Box::new(DynamicTree::default()));
+        world.constraints = record
+            .constraints
+            .into_iter()
+            .map(|entry| {
+                let kind = match entry.kind {
+                    ConstraintKindRecord::Pair(record) => ConstraintKind::Pair(record.into_boxed()),
+                    ConstraintKindRecord::Unary(record) => {
+                        ConstraintKind::Unary(record.into_boxed())
+                    }
+                    ConstraintKindRecord::Area(constraint) => ConstraintKind::Area(constraint),
+                };
+                let constraint = ConstraintEntry {
+                    kind,
+                    break_threshold: entry.break_threshold,
+                    load: entry.load,
+                };
+                (entry.handle, constraint)
+            })
+            .collect();
+        world.last_constraint_id = record.last_constraint_id;
+        world.force_generators = record
+            .force_generators
+            .into_iter()
+            .map(|(handle, generator)| (handle, generator.into_boxed()))
+            .collect();
+        world.last_force_generator_id = record.last_force_generator_id;
+        world.solver_iterations = record.solver_iterations;
+        world.solver_mode = record.solver_mode;
+        world.contact_compliance = record.contact_compliance;
+        world.disabled_collisions = record.disabled_collisions.into_iter().collect();
+        Ok(world)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraint::{
+        AreaConstraint, BungeeSpring, DistanceJoint, RevoluteJoint, TargetJoint,
+    };
+    use crate::bsp::sweep_and_prune::SweepAndPrune;
+    use crate::force_generator::BarnesHutGravity;
+
+    const DELTA: std::time::Duration = std::time::Duration::from_millis(16);
+
+    fn pendulum(world: &mut World) -> (Entity, Entity) {
+        let anchor = world.add(Body::new(
+            Shape::Square { edge_length: 1.0 },
+            Vec2::new(),
+            Mass::Infinity,
+        ));
+        let bob = world.add(Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(0.0, -10.0),
+            Mass::Value(1.0),
+        ));
+        (anchor, bob)
+    }
+
+    // A circle of radius 2 at the origin and a square of edge 2 at (10, 0)
+    fn query_world() -> (World, Entity, Entity) {
+        let mut world = World::new();
+        let circle = world.add(Body::new(
+            Shape::Circle { radius: 2.0 },
+            Vec2::new(),
+            Mass::Value(1.0),
+        ));
+        let square = world.add(Body::new(
+            Shape::Square { edge_length: 2.0 },
+            Vec2::new_at(10.0, 0.0),
+            Mass::Infinity,
+        ));
+        (world, circle, square)
+    }
+
+    #[test]
+    fn test_query_point() {
+        let (world, circle, square) = query_world();
+        assert_eq!(vec![circle], world.query_point(Vec2::new_at(1.0, 1.0)));
+        assert_eq!(vec![square], world.query_point(Vec2::new_at(10.9, -0.9)));
+        assert!(world.query_point(Vec2::new_at(5.0, 0.0)).is_empty());
+        // Inside the bounding box of the circle, but outside the circle
+        assert!(world.query_point(Vec2::new_at(1.8, 1.8)).is_empty());
+    }
+
+    #[test]
+    fn test_query_aabb() {
+        let (world, circle, square) = query_world();
+        let mut found = world.query_aabb(&Aabb::new(-1.0, 9.5, 1.0, -1.0));
+        found.sort_by_key(Entity::index);
+        assert_eq!(vec![circle, square], found);
+        assert_eq!(vec![square], world.query_aabb(&Aabb::new(8.0, 12.0, 5.0, 0.5)));
+        assert!(world.query_aabb(&Aabb::new(3.0, 8.0, 1.0, -1.0)).is_empty());
+    }
+
+    #[test]
+    fn test_intersect_shape() {
+        let (world, circle, square) = query_world();
+        let probe = Shape::Circle { radius: 1.0 };
+        assert_eq!(vec![circle], world.intersect_shape(probe, Vec2::new_at(2.5, 0.0)));
+        assert_eq!(vec![square], world.intersect_shape(probe, Vec2::new_at(11.5, 1.5)));
+        assert!(world.intersect_shape(probe, Vec2::new_at(5.0, 0.0)).is_empty());
+
+        // Only the bounding boxes overlap, next to a corner of the square
+        assert!(world.intersect_shape(probe, Vec2::new_at(11.8, 1.8)).is_empty());
+        // And next to the circle
+        let corner = Shape::Square { edge_length: 1.0 };
+        assert!(world.intersect_shape(corner, Vec2::new_at(2.0, 2.0)).is_empty());
+
+        // Concentric, whatever the sizes
+        assert_eq!(vec![circle], world.intersect_shape(corner, Vec2::new()));
+        let large = Shape::Square { edge_length: 8.0 };
+        assert_eq!(vec![square], world.intersect_shape(large, Vec2::new_at(10.0, 0.0)));
+        let tiny = Shape::Circle { radius: 0.1 };
+        assert_eq!(vec![square], world.intersect_shape(tiny, Vec2::new_at(10.0, 0.0)));
+        let ring = Shape::Circle { radius: 3.0 };
+        assert_eq!(vec![circle], world.intersect_shape(ring, Vec2::new()));
+    }
+
+    #[test]
+    fn test_intersect_rotated_square() {
+        let (mut world, _, square) = query_world();
+        let probe = Shape::Circle { radius: 0.2 };
+        // Right of the square, where only its corner reaches once it is turned
+        let position = Vec2::new_at(11.3, 0.0);
+        assert!(world.intersect_shape(probe, position).is_empty());
+
+        world.with_body_mut(square, |body| body.angle = std::f64::consts::FRAC_PI_4);
+        assert_eq!(vec![square], world.intersect_shape(probe, position));
+        // And the other way round, inside the axis aligned box but past the turned edge
+        assert!(world.intersect_shape(probe, Vec2::new_at(10.9, 0.9)).is_empty());
+    }
+
+    #[test]
+    fn test_rotated_square_distance_queries() {
+        let (mut world, circle, square) = query_world();
+        world.with_body_mut(square, |body| body.angle = std::f64::consts::FRAC_PI_4);
+        let corner = 10.0 - 2.0_f64.sqrt();
+
+        assert!((world.distance(circle, square).unwrap() - (corner - 2.0)).abs() < 1e-9);
+        let points = world.closest_points(circle, square).unwrap();
+        assert!((points.points[1] - Vec2::new_at(corner, 0.0)).len() < 1e-9);
+
+        // Hits the corner reaching out to the left, well before the unturned face at x = 9
+        let hit = world
+            .cast_shape(
+                Shape::Circle { radius: 1.0 },
+                Vec2::new_at(4.0, 0.0),
+                Vec2::new_at(10.0, 0.0),
+                |_, _| true,
+            )
+            .unwrap();
+        assert_eq!(square, hit.entity);
+        assert!((hit.intersection.toi - (corner - 5.0) / 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sweep_and_prune_reused_slot() {
+        let mut world = World::with_broadphase(Box::new(SweepAndPrune::new()));
+        let (anchor, bob) = pendulum(&mut world);
+        world.update(DELTA);
+
+        world.remove(bob);
+        let reused = world.add(Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(5.0, 5.0),
+            Mass::Value(1.0),
+        ));
+        assert_eq!(bob.index(), reused.index());
+        world.update(DELTA);
+
+        // The new body still collides with the others
+        world.with_body_mut(reused, |body| body.position = Vec2::new_at(0.5, 0.0));
+        let collisions = world.update(DELTA).into_iter().filter(|event| {
+            matches!(event, WorldEvent::Collision(data) if data.ids.contains(&anchor))
+        });
+        assert_eq!(1, collisions.count());
+    }
+
+    #[test]
+    fn test_breakable_constraint() {
+        let mut world = World::new();
+        let (anchor, bob) = pendulum(&mut world);
+        let handle = world.add_constraint(Box::new(DistanceJoint::new(anchor, bob, 10.0)));
+        world.set_break_threshold(handle, Some(150.0));
+
+        for _ in 0..60 {
+            world.apply_force(bob, Vec2::new_at(0.0, -100.0));
+            let events = world.update(DELTA);
+            assert!(events.is_empty());
+        }
+        assert!((world.constraint_load(handle).unwrap() - 100.0).abs() < 10.0);
+
+        let mut broken = vec![];
+        for _ in 0..10 {
+            world.apply_force(bob, Vec2::new_at(0.0, -1000.0));
+            for event in world.update(DELTA) {
+                if let WorldEvent::ConstraintBroken { handle, load } = event {
+                    assert!(load > 150.0);
+                    broken.push(handle);
+                }
+            }
+        }
+
+        assert_eq!(vec![handle], broken);
+        assert!(world.constraint(handle).is_none());
+        assert!(world.remove_constraint(handle).is_none());
+    }
+
+    #[test]
+    fn test_constraint_gives_way_within_the_step() {
+        let mut world = World::new();
+        let (anchor, bob) = pendulum(&mut world);
+        let handle = world.add_constraint(Box::new(DistanceJoint::new(anchor, bob, 10.0)));
+        world.set_break_threshold(handle, Some(150.0));
+        world.with_body_mut(bob, |body| body.velocity = Vec2::new_at(0.0, -20.0));
+
+        let events = world.update(DELTA);
+        assert!(matches!(
+            events.as_slice(),
+            [WorldEvent::ConstraintBroken { load, .. }] if *load > 150.0
+        ));
+
+        // Only the threshold worth of impulse slowed the bob down before the joint broke
+        let delta = DELTA.as_secs_f64();
+        let solved_velocity = -20.0 + 150.0 * delta;
+        let body = world.body(bob).unwrap();
+        assert!((body.position.y - (-10.0 + solved_velocity * delta)).abs() < 1e-9);
+        let friction = crate::math::powf(body.friction, delta);
+        assert!((body.velocity.y - solved_velocity * friction).abs() < 1e-9);
+        assert_eq!(0.0, body.velocity.x);
+    }
+
+    #[test]
+    #[should_panic(expected = "two different bodies")]
+    fn test_constraint_on_a_single_body() {
+        let mut world = World::new();
+        let (_, bob) = pendulum(&mut world);
+        world.add_constraint(Box::new(DistanceJoint::new(bob, bob, 1.0)));
+    }
+
+    #[test]
+    fn test_constraint_handles() {
+        let mut world = World::new();
+        let (anchor, bob) = pendulum(&mut world);
+        let distance = world.add_constraint(Box::new(DistanceJoint::new(anchor, bob, 10.0)));
+        let hinge = world.add_constraint(Box::new(RevoluteJoint::new(
+            anchor,
+            bob,
+            Vec2::new(),
+            Vec2::new_at(0.0, 10.0),
+        )));
+        assert_ne!(distance, hinge);
+        assert_eq!(2, world.constraints().count());
+
+        let joint = world.constraint_mut(hinge).unwrap();
+        let joint = joint.as_any_mut().downcast_mut::<RevoluteJoint>().unwrap();
+        joint.enable_motor = true;
+
+        let joint = world.constraint(hinge).unwrap();
+        assert_eq!(bob, joint.get_second_entity());
+        assert!(joint.as_any().downcast_ref::<RevoluteJoint>().unwrap().enable_motor);
+        assert!(joint.as_any().downcast_ref::<DistanceJoint>().is_none());
+
+        assert!(world.remove_constraint(distance).is_some());
+        assert!(world.constraint(distance).is_none());
+        let handles: Vec<_> = world.constraints().map(|(handle, _)| handle).collect();
+        assert_eq!(vec![hinge], handles);
+    }
+
+    #[test]
+    fn test_constraint_kinds_share_one_store() {
+        let mut world = World::new();
+        world.set_solver_mode(SolverMode::Xpbd { substeps: 2 });
+        let (anchor, bob) = pendulum(&mut world);
+        let other = world.add(Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(5.0, -10.0),
+            Mass::Value(1.0),
+        ));
+        let pair = world.add_constraint(Box::new(DistanceJoint::new(anchor, bob, 10.0)));
+        let target = world.add_unary_constraint(Box::new(TargetJoint::new(
+            other,
+            Vec2::new_at(5.0, 0.0),
+            1000.0,
+        )));
+        let area = world.add_area_constraint(AreaConstraint::new(vec![anchor, bob, other], 30.0));
+
+        let kinds: Vec<_> = world
+            .constraints()
+            .map(|(handle, kind)| {
+                let name = match kind {
+                    ConstraintKind::Pair(_) => "pair",
+                    ConstraintKind::Unary(_) => "unary",
+                    ConstraintKind::Area(_) => "area",
+                };
+                (handle, name)
+            })
+            .collect();
+        assert_eq!(vec![(pair, "pair"), (target, "unary"), (area, "area")], kinds);
+        assert!(world.constraint(target).is_none());
+        assert!(world.unary_constraint(pair).is_none());
+
+        // Every kind reports its load and breaks under it the same way
+        world.set_break_threshold(target, Some(1.0));
+        world.set_break_threshold(area, Some(1.0e-6));
+        let broken: Vec<_> = world
+            .update(DELTA)
+            .into_iter()
+            .filter_map(|event| match event {
+                WorldEvent::ConstraintBroken { handle, .. } => Some(handle),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(vec![target, area], broken);
+        let handles: Vec<_> = world.constraints().map(|(handle, _)| handle).collect();
+        assert_eq!(vec![pair], handles);
+
+        assert!(matches!(world.remove_constraint(pair), Some(ConstraintKind::Pair(_))));
+        assert_eq!(0, world.constraints().count());
+    }
+
+    #[test]
+    fn test_target_joint() {
+        let mut world = World::new();
+        let body = world.add(Body::new(
+            Shape::Square { edge_length: 2.0 },
+            Vec2::new(),
+            Mass::Value(1.0),
+        ));
+        let joint = TargetJoint::at_point(
+            body,
+            &world.body(body).unwrap(),
+            Vec2::new_at(1.0, 1.0),
+            1000.0,
+        );
+        let handle = world.add_unary_constraint(Box::new(joint));
+
+        let target = world.unary_constraint_mut(handle).unwrap();
+        let target = target.as_any_mut().downcast_mut::<TargetJoint>().unwrap();
+        target.target = Vec2::new_at(21.0, 1.0);
+        for _ in 0..120 {
+            world.update(DELTA);
+        }
+        let dragged = world.body(body).unwrap();
+        let anchor = dragged.position + Vec2::new_at(1.0, 1.0).rotated(dragged.angle);
+        assert!((anchor - Vec2::new_at(21.0, 1.0)).len() < 0.5, "{anchor:?}");
+
+        // Grabbed off center, the body swings around under gravity
+        for _ in 0..120 {
+            world.apply_force(body, Vec2::new_at(0.0, -100.0));
+            world.update(DELTA);
+        }
+        let hanging = world.body(body).unwrap();
+        assert!(hanging.angle.abs() > 0.1);
+        assert!(hanging.position.y < 0.0);
+
+        // Too weak to hold on against the gravity
+        let target = world.unary_constraint_mut(handle).unwrap();
+        let target = target.as_any_mut().downcast_mut::<TargetJoint>().unwrap();
+        target.max_force = 50.0;
+        for _ in 0..60 {
+            world.apply_force(body, Vec2::new_at(0.0, -100.0));
+            world.update(DELTA);
+            let impulse = world.unary_constraint(handle).unwrap().applied_impulse();
+            assert!(impulse <= 50.0 * DELTA.as_secs_f64() + 1e-9);
+        }
+        assert!(world.body(body).unwrap().position.y < hanging.position.y - 5.0);
+
+        assert!(world.remove_constraint(handle).is_some());
+        assert!(world.unary_constraint(handle).is_none());
+    }
+
+    // Bit patterns of everything observable about the bodies
+    fn body_bits(world: &World) -> Vec<(Entity, [u64; 6])> {
+        world
+            .entities()
+            .map(|(entity, body)| {
+                let state = [
+                    body.position.x,
+                    body.position.y,
+                    body.velocity.x,
+                    body.velocity.y,
+                    body.angle,
+                    body.angular_velocity,
+                ];
+                (entity, state.map(f64::to_bits))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut world = World::new();
+        let (anchor, bob) = pendulum(&mut world);
+        world.add_constraint(Box::new(DistanceJoint::new(anchor, bob, 10.0)));
+        for index in 0..20 {
+            let body = world.add(Body::new(
+                Shape::Circle { radius: 1.0 },
+                Vec2::new_at(index as f64 * 1.5, 5.0 + index as f64 * 0.7),
+                Mass::Value(1.0),
+            ));
+            if index % 3 == 0 {
+                world.remove(body);
+            }
+        }
+        world.add_force_generator(Box::new(BarnesHutGravity::new(50.0, 0.5)));
+        world.add_unary_constraint(Box::new(TargetJoint::new(
+            bob,
+            Vec2::new_at(5.0, -5.0),
+            100.0,
+        )));
+
+        let run = |world: &mut World| {
+            let mut collisions = 0;
+            for _ in 0..60 {
+                world.apply_force(bob, Vec2::new_at(0.0, -100.0));
+                collisions += world.update(DELTA).len();
+            }
+            let added = world.add(Body::new(
+                Shape::Square { edge_length: 1.0 },
+                Vec2::new_at(0.0, 50.0),
+                Mass::Value(1.0),
+            ));
+            (collisions, added, body_bits(world))
+        };
+
+        for _ in 0..10 {
+            world.update(DELTA);
+        }
+        let snapshot = world.snapshot();
+        let first = run(&mut world);
+        assert!(first.0 > 0);
+
+        world.restore(&snapshot);
+        assert_eq!(first, run(&mut world));
+
+        // Also after the world got changed in between
+        world.remove(bob);
+        world.set_solver_mode(SolverMode::Xpbd { substeps: 4 });
+        world.restore(&snapshot);
+        assert_eq!(first, run(&mut world));
+    }
+
+    // Pins the hashes of both solvers, which only hold across platforms with the software
+    // math, so it runs with `cargo test --features deterministic`
+    #[cfg(feature = "deterministic")]
+    #[test]
+    fn test_state_hash_matches_across_runs() {
+        use crate::{constraint::DampedSpring, force_generator::Gravity};
+
+        let run = |solver_mode| {
+            let mut world = World::new();
+            world.set_solver_mode(solver_mode);
+            let (anchor, bob) = pendulum(&mut world);
+            world.add_constraint(Box::new(DampedSpring::new(anchor, bob, 8.0, 50.0, 2.0)));
+            let wheel = world.add(Body::new(
+                Shape::Square { edge_length: 2.0 },
+                Vec2::new_at(6.0, -10.0),
+                Mass::Value(2.0),
+            ));
+            world.add_constraint(Box::new(RevoluteJoint::new(
+                bob,
+                wheel,
+                Vec2::new_at(3.0, 0.0),
+                Vec2::new_at(-3.0, 0.0),
+            )));
+            world.add(Body::new(
+                Shape::Square { edge_length: 40.0 },
+                Vec2::new_at(0.0, -40.0),
+                Mass::Infinity,
+            ));
+            for index in 0..30 {
+                let mut body = Body::new(
+                    Shape::Circle { radius: 1.0 },
+                    Vec2::new_at((index % 6) as f64 * 2.5 - 7.0, (index / 6) as f64 * 2.5),
+                    Mass::Value(1.0),
+                );
+                body.velocity = Vec2::new_polar(index as f64, 3.0);
+                world.add(body);
+            }
+            world.add_force_generator(Box::new(Gravity {
+                acceleration: Vec2::new_at(0.0, -10.0),
+            }));
+
+            (0..120)
+                .map(|_| {
+                    world.update(DELTA);
+                    world.state_hash()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let impulse = run(SolverMode::Impulse);
+        let xpbd = run(SolverMode::Xpbd { substeps: 4 });
+        assert_eq!(impulse, run(SolverMode::Impulse));
+        assert_eq!(xpbd, run(SolverMode::Xpbd { substeps: 4 }));
+        assert_ne!(impulse[119], xpbd[119]);
+
+        // Any change to the simulation itself has to update these
+        assert_eq!(
+            (11_174_793_813_011_665_941, 10_292_605_682_617_435_477),
+            (impulse[119], xpbd[119])
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        use crate::constraint::DampedSpring;
+
+        let mut world = World::new();
+        world.set_solver_mode(SolverMode::Xpbd { substeps: 2 });
+        let (anchor, bob) = pendulum(&mut world);
+        let removed = world.add(Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new(),
+            Mass::Infinity,
+        ));
+        world.remove(removed);
+        let other = world.add(Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(1.0, -10.0),
+            Mass::Value(2.0),
+        ));
+        let joint = world.add_constraint(Box::new(DistanceJoint::new(anchor, bob, 10.0)));
+        world.set_break_threshold(joint, Some(1.0e4));
+        world.add_constraint(Box::new(DampedSpring::new(bob, other, 3.0, 50.0, 1.0)));
+        world.add_area_constraint(AreaConstraint::new(vec![anchor, bob, other], 10.0));
+        world.add_unary_constraint(Box::new(TargetJoint::new(other, Vec2::new(), 10.0)));
+        world.add_force_generator(Box::new(BarnesHutGravity::new(1.0, 0.5)));
+        world.disable_collision(bob, other);
+        for _ in 0..10 {
+            world.update(DELTA);
+        }
+
+        let json = serde_json::to_string(&world).unwrap();
+        let mut loaded: World = serde_json::from_str(&json).unwrap();
+        assert_eq!(json, serde_json::to_string(&loaded).unwrap());
+        assert!(loaded.is_collision_disabled(bob, other));
+        assert!(loaded.body(removed).is_none());
+
+        // Both keep handing out the same handles and move the same way
+        let body = Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(0.0, 20.0),
+            Mass::Value(1.0),
+        );
+        assert_eq!(world.add(body), loaded.add(body));
+        assert_eq!(
+            world.add_constraint(Box::new(DistanceJoint::new(anchor, other, 10.0))),
+            loaded.add_constraint(Box::new(DistanceJoint::new(anchor, other, 10.0)))
+        );
+        for _ in 0..30 {
+            world.update(DELTA);
+            loaded.update(DELTA);
+        }
+        assert_eq!(body_bits(&world), body_bits(&loaded));
+
+        // Constraint types outside of the registry can't be saved
+        #[derive(Clone)]
+        struct Unregistered(Entity, Entity);
+        impl Constraint for Unregistered {
+            fn get_first_entity(&self) -> Entity {
+                self.0
+            }
+            fn get_second_entity(&self) -> Entity {
+                self.1
+            }
+        }
+        world.add_constraint(Box::new(Unregistered(anchor, bob)));
+        assert!(serde_json::to_string(&world).is_err());
+    }
+
+    fn stiff_chain(mode: SolverMode) -> World {
+        let mut world = World::new();
+        world.set_solver_mode(mode);
+        let mut previous = world.add(Body::new(
+            Shape::Square { edge_length: 1.0 },
+            Vec2::new(),
+            Mass::Infinity,
+        ));
+
+        for index in 1..=5 {
+            let link = world.add(Body::new(
+                Shape::Circle { radius: 0.5 },
+                Vec2::new_at(2.0 * index as f64, 0.0),
+                Mass::Value(1.0),
+            ));
+            let mut spring = BungeeSpring::new(previous, link, 2.0);
+            spring.spring_constant = 1.0e6;
+            world.add_constraint(Box::new(spring));
+            previous = link;
+        }
+
+        for _ in 0..120 {
+            for entity in world.ids().collect::<Vec<_>>() {
+                world.apply_force(entity, Vec2::new_at(0.0, -100.0));
+            }
+            world.update(DELTA);
+        }
+        world
+    }
+
+    #[test]
+    fn test_xpbd_keeps_stiff_springs_stable() {
+        // Explicit forces at this stiffness blow up within a few steps
+        let world = stiff_chain(SolverMode::Xpbd { substeps: 8 });
+        let positions: Vec<Vec2> = world.bodies().map(|body| body.position).collect();
+        for link in positions.windows(2) {
+            assert!((link[1] - link[0]).len() < 2.1);
+        }
+        // The chain swung down from the anchor without stretching
+        assert!(positions[5].y < -1.0);
+        assert!(positions[5].len() < 10.5);
+    }
+
+    #[test]
+    fn test_xpbd_area_constraint() {
+        let mut world = World::new();
+        world.set_solver_mode(SolverMode::Xpbd { substeps: 4 });
+        let corners = [(-5.0, -5.0), (5.0, -5.0), (5.0, 5.0), (-5.0, 5.0)];
+        let entities: Vec<Entity> = corners
+            .iter()
+            .map(|(x, y)| {
+                let body = Body::new(
+                    Shape::Circle { radius: 1.0 },
+                    Vec2::new_at(*x, *y),
+                    Mass::Value(1.0),
+                );
+                world.add(body)
+            })
+            .collect();
+        let handle = world.add_area_constraint(AreaConstraint::new(entities.clone(), 100.0));
+
+        for _ in 0..60 {
+            for entity in &entities {
+                let inwards = world.body(*entity).unwrap().position * -10.0;
+                world.apply_force(*entity, inwards);
+            }
+            world.update(DELTA);
+        }
+
+        let positions: Vec<Vec2> = world.bodies().map(|body| body.position).collect();
+        let area = AreaConstraint::area(&positions);
+        assert!((area - 100.0).abs() < 1.0, "{area}");
+        assert!(world.area_constraint(handle).is_some());
+        assert!(world.remove_constraint(handle).is_some());
+        assert!(world.area_constraint(handle).is_none());
+    }
+
+    #[test]
+    fn test_xpbd_contacts() {
+        let mut world = World::new();
+        world.set_solver_mode(SolverMode::Xpbd { substeps: 4 });
+        world.add(Body::new(
+            Shape::Square { edge_length: 2.0 },
+            Vec2::new(),
+            Mass::Infinity,
+        ));
+        let ball = world.add(Body::new(
+            Shape::Circle { radius: 1.0 },
+            Vec2::new_at(0.0, 5.0),
+            Mass::Value(1.0),
+        ));
+
+        let mut touched = false;
+        for _ in 0..240 {
+            world.apply_force(ball, Vec2::new_at(0.0, -100.0));
+            touched |= !world.update(DELTA).is_empty();
+        }
+
+        assert!(touched);
+        let ball = world.body(ball).unwrap();
+        assert!((ball.position.y - 2.0).abs() < 0.1, "{}", ball.position.y);
+        assert!(ball.velocity.len() < 1.0);
+    }
+
+    #[test]
+    fn test_xpbd_contact_compliance() {
+        let resting_height = |compliance: f64| {
+            let mut world = World::new();
+            world.set_solver_mode(SolverMode::Xpbd { substeps: 4 });
+            world.set_contact_compliance(compliance);
+            world.add(Body::new(
+                Shape::Square { edge_length: 2.0 },
+                Vec2::new(),
+                Mass::Infinity,
+            ));
+            let ball = world.add(Body::new(
+                Shape::Circle { radius: 1.0 },
+                Vec2::new_at(0.0, 2.0),
+                Mass::Value(1.0),
+            ));
+            for _ in 0..240 {
+                world.apply_force(ball, Vec2::new_at(0.0, -100.0));
+                world.update(DELTA);
+            }
+            world.body(ball).unwrap().position.y
+        };
+
+        // A soft contact sinks in until it pushes back with the weight of the ball
+        let rigid = resting_height(0.0);
+        let soft = resting_height(1.0e-3);
+        assert!((rigid - 2.0).abs() < 0.02, "{rigid}");
+        assert!((rigid - soft - 0.1).abs() < 0.03, "{soft}");
+    }
 }